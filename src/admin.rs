@@ -0,0 +1,445 @@
+use std::time::{Duration, Instant};
+
+use base64::prelude::*;
+use meshtastic::Message;
+use meshtastic::protobufs::{Channel, ChannelSet, ChannelSettings, admin_message, channel::Role};
+use presage::libsignal_service::groups_v2::Role as GroupRole;
+use tracing::info;
+
+use crate::channels::{CHANNEL_SLOTS, ChannelTable};
+use crate::config::Config;
+use crate::{Model, Uuid};
+
+/// How long a `/channels` change waits for its confirm before it gets thrown out
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+// the firmware wont take anything longer
+const MAX_CHANNEL_NAME: usize = 11;
+
+const CHANNELS_USAGE: &str = "usage:
+/channels list
+/channels add <name> [psk]
+/channels set <index> uplink|downlink on|off
+/channels rotate-psk <index>
+/channels import <meshtastic url>
+/channels confirm | cancel
+with more than one radio, put its name before the rest to pick one other than the first";
+
+#[derive(Debug)]
+enum ChannelCommand {
+  List,
+  Add { name: String, psk: Option<Vec<u8>> },
+  Set { index: u32, uplink: bool, on: bool },
+  RotatePsk { index: u32 },
+  Import { url: String },
+  Confirm,
+  Cancel,
+}
+
+/// A change someone asked for that hasnt been confirmed yet
+#[derive(Debug)]
+pub struct PendingChannels {
+  radio: usize,
+  requested_by: Uuid,
+  requested_at: Instant,
+  channels: Vec<Channel>,
+  description: String,
+  /// we made up a key, which nobody knows yet
+  generated_psk: bool,
+}
+
+/// `/channels [radio] ...`, the name only matters to pick a radio other than the first
+fn pick_radio<'a>(model: &Model, args: &'a str) -> (usize, &'a str) {
+  let trimmed = args.trim_start();
+  let (first, rest) = trimmed.split_once(char::is_whitespace).unwrap_or((trimmed, ""));
+  match model
+    .radios
+    .iter()
+    .position(|radio| radio.name.eq_ignore_ascii_case(first))
+  {
+    Some(radio) => (radio, rest),
+    None => (0, args),
+  }
+}
+
+fn parse(args: &str) -> Result<ChannelCommand, String> {
+  let args: Vec<&str> = args.split_whitespace().collect();
+  let index = |arg: &str| -> Result<u32, String> {
+    arg
+      .parse::<u32>()
+      .ok()
+      .filter(|index| (*index as usize) < CHANNEL_SLOTS)
+      .ok_or_else(|| format!("not a channel index: {}", arg))
+  };
+
+  Ok(match args.as_slice() {
+    [] | ["list"] => ChannelCommand::List,
+    ["add", name] => ChannelCommand::Add {
+      name: name.to_string(),
+      psk: None,
+    },
+    ["add", name, psk] => ChannelCommand::Add {
+      name: name.to_string(),
+      psk: Some(decode_psk(psk)?),
+    },
+    ["set", slot, which @ ("uplink" | "downlink"), on @ ("on" | "off")] => ChannelCommand::Set {
+      index: index(slot)?,
+      uplink: *which == "uplink",
+      on: *on == "on",
+    },
+    ["rotate-psk", slot] => ChannelCommand::RotatePsk { index: index(slot)? },
+    ["import", url] => ChannelCommand::Import { url: url.to_string() },
+    ["confirm"] => ChannelCommand::Confirm,
+    ["cancel"] => ChannelCommand::Cancel,
+    _ => return Err(CHANNELS_USAGE.to_string()),
+  })
+}
+
+/// Psks the way the apps show them, base64 of 0, 1, 16 or 32 bytes
+fn decode_psk(psk: &str) -> Result<Vec<u8>, String> {
+  let bytes = BASE64_STANDARD
+    .decode(psk)
+    .or_else(|_| BASE64_URL_SAFE_NO_PAD.decode(psk))
+    .map_err(|_| "psk should be base64".to_string())?;
+  match bytes.len() {
+    0 | 1 | 16 | 32 => Ok(bytes),
+    len => Err(format!("psk should be 16 or 32 bytes, not {}", len)),
+  }
+}
+
+/// A fresh aes256 key. `generate_rand_id` comes from the thread rng, which is good enough for keys
+fn random_psk() -> Vec<u8> {
+  (0..8)
+    .flat_map(|_| meshtastic::utils::generate_rand_id::<u32>().to_le_bytes())
+    .collect()
+}
+
+/// Channels out of a meshtastic.org/e/#... url, primary first
+fn parse_channel_url(url: &str) -> Result<Vec<ChannelSettings>, String> {
+  let (_, encoded) = url.split_once('#').ok_or("thats not a channel url")?;
+  let bytes = BASE64_URL_SAFE_NO_PAD
+    .decode(encoded.trim_end_matches('='))
+    .map_err(|_| "couldnt decode the channel url".to_string())?;
+  let set = ChannelSet::decode(bytes.as_slice()).map_err(|_| "couldnt decode the channel url".to_string())?;
+  if set.settings.is_empty() || set.settings.len() > CHANNEL_SLOTS {
+    return Err("channel url has no channels in it".to_string());
+  }
+  Ok(set.settings)
+}
+
+fn describe(channels: &ChannelTable, index: u32, channel: &Channel) -> String {
+  let Some(settings) = &channel.settings else {
+    return format!("#{} (disabled)", index);
+  };
+  let role = if channel.role() == Role::Primary {
+    "primary"
+  } else {
+    "secondary"
+  };
+  let mut flags = vec![role];
+  if settings.uplink_enabled {
+    flags.push("uplink");
+  }
+  if settings.downlink_enabled {
+    flags.push("downlink");
+  }
+  if settings.psk.is_empty() {
+    flags.push("unencrypted");
+  }
+  format!("#{} {} ({})", index, channels.display_name(settings), flags.join(", "))
+}
+
+fn existing(channels: &ChannelTable, index: u32) -> Result<Channel, String> {
+  channels
+    .get(index)
+    .filter(|channel| channel.settings.is_some())
+    .cloned()
+    .ok_or_else(|| format!("theres no channel #{}", index))
+}
+
+/// What the radio is about to be sent, what to call it, and whether we made up a psk for it
+struct Plan {
+  channels: Vec<Channel>,
+  description: String,
+  generated_psk: bool,
+}
+
+/// Works out what to send the radio for a change, without sending anything yet
+fn plan(command: ChannelCommand, channels: &ChannelTable) -> Result<Plan, String> {
+  match command {
+    ChannelCommand::Add { name, psk } => {
+      if name.len() > MAX_CHANNEL_NAME {
+        return Err(format!("channel names can only be {} bytes", MAX_CHANNEL_NAME));
+      }
+      if channels.find(&name).is_some() {
+        return Err(format!("theres already a channel called {}", name));
+      }
+      // slot 0 is always the primary
+      let index = (1..CHANNEL_SLOTS as u32)
+        .find(|index| channels.get(*index).is_none())
+        .ok_or("all the channel slots are taken")?;
+      let generated_psk = psk.is_none();
+      let channel = Channel {
+        index: index as i32,
+        settings: Some(ChannelSettings {
+          name: name.clone(),
+          psk: psk.unwrap_or_else(random_psk),
+          ..Default::default()
+        }),
+        role: Role::Secondary.into(),
+      };
+      Ok(Plan {
+        channels: vec![channel],
+        description: format!("add channel {} as #{}", name, index),
+        generated_psk,
+      })
+    }
+
+    ChannelCommand::Set { index, uplink, on } => {
+      let mut channel = existing(channels, index)?;
+      let settings = channel.settings.as_mut().expect("checked by existing");
+      let flag = if uplink {
+        &mut settings.uplink_enabled
+      } else {
+        &mut settings.downlink_enabled
+      };
+      *flag = on;
+      let description = format!(
+        "turn {} {} for #{} {}",
+        if uplink { "uplink" } else { "downlink" },
+        if on { "on" } else { "off" },
+        index,
+        channels.display_name(settings)
+      );
+      Ok(Plan {
+        channels: vec![channel],
+        description,
+        generated_psk: false,
+      })
+    }
+
+    ChannelCommand::RotatePsk { index } => {
+      let mut channel = existing(channels, index)?;
+      let settings = channel.settings.as_mut().expect("checked by existing");
+      settings.psk = random_psk();
+      let description = format!(
+        "give #{} {} a new random psk. every node on it will need the new one",
+        index,
+        channels.display_name(settings)
+      );
+      Ok(Plan {
+        channels: vec![channel],
+        description,
+        generated_psk: true,
+      })
+    }
+
+    ChannelCommand::Import { url } => {
+      let settings = parse_channel_url(&url)?;
+      let names: Vec<String> = settings
+        .iter()
+        .map(|settings| channels.display_name(settings).to_string())
+        .collect();
+      // same as the apps, an import replaces every channel
+      let imported = (0..CHANNEL_SLOTS)
+        .map(|index| match settings.get(index) {
+          Some(settings) => {
+            let role = if index == 0 { Role::Primary } else { Role::Secondary };
+            Channel {
+              index: index as i32,
+              settings: Some(settings.clone()),
+              role: role.into(),
+            }
+          }
+          None => Channel {
+            index: index as i32,
+            settings: None,
+            role: Role::Disabled.into(),
+          },
+        })
+        .collect();
+      Ok(Plan {
+        channels: imported,
+        description: format!("replace every channel with {}", names.join(", ")),
+        generated_psk: false,
+      })
+    }
+
+    ChannelCommand::List | ChannelCommand::Confirm | ChannelCommand::Cancel => {
+      unreachable!("not a change")
+    }
+  }
+}
+
+/// Group admins are the only ones who get to touch the radio
+pub fn is_admin(model: &Model, config: &Config, uuid: &Uuid) -> bool {
+  model.groups.get(&config.group_key).is_some_and(|group| {
+    group
+      .members
+      .iter()
+      .any(|member| Uuid::from(member.aci) == *uuid && member.role == GroupRole::Administrator)
+  })
+}
+
+/// Anyone in the bridged group, the only people the bridge takes commands from in a 1:1 chat
+pub fn is_member(model: &Model, config: &Config, uuid: &Uuid) -> bool {
+  model
+    .groups
+    .get(&config.group_key)
+    .is_some_and(|group| group.members.iter().any(|member| Uuid::from(member.aci) == *uuid))
+}
+
+/// What `/channels` turned into, either something to tell the group or a confirmed change
+#[derive(Debug)]
+pub enum ChannelsOutcome {
+  Reply(String),
+  Apply {
+    radio: usize,
+    channels: Vec<Channel>,
+    description: String,
+    /// who gets the new psk once its on the radio, never the whole group
+    share_psk_with: Option<Uuid>,
+  },
+}
+
+/// `/channels ...` from the group. Anything that changes the radio gets parked until the same
+/// admin confirms it, so a typo cant take the mesh down.
+pub fn handle_channels_command(model: &mut Model, config: &Config, sender: Uuid, args: &str) -> ChannelsOutcome {
+  let (radio, args) = pick_radio(model, args);
+  let command = match parse(args) {
+    Ok(command) => command,
+    Err(err) => return ChannelsOutcome::Reply(err),
+  };
+
+  if let ChannelCommand::List = command {
+    let channels = &model.radios[radio].channels;
+    let lines: Vec<String> = channels
+      .iter()
+      .map(|(index, channel)| describe(channels, index, channel))
+      .collect();
+    return ChannelsOutcome::Reply(if lines.is_empty() {
+      "dont know the radios channels yet".to_string()
+    } else {
+      lines.join("\n")
+    });
+  }
+
+  if !is_admin(model, config, &sender) {
+    return ChannelsOutcome::Reply("only group admins can change channels".to_string());
+  }
+
+  // anything stale is as good as cancelled
+  if let Some(pending) = &model.pending_channels {
+    if pending.requested_at.elapsed() > CONFIRM_TIMEOUT {
+      model.pending_channels = None;
+    }
+  }
+
+  // one change at a time, someone elses doesnt just get thrown away
+  if !matches!(command, ChannelCommand::Confirm | ChannelCommand::Cancel)
+    && let Some(pending) = model
+      .pending_channels
+      .as_ref()
+      .filter(|pending| pending.requested_by != sender)
+  {
+    return ChannelsOutcome::Reply(format!(
+      "{} has a change waiting: {}\nthey can /channels confirm it, or /channels cancel it first",
+      model.names.get(&pending.requested_by),
+      pending.description
+    ));
+  }
+
+  match command {
+    ChannelCommand::Confirm => match model.pending_channels.take() {
+      Some(pending) if pending.requested_by == sender => {
+        info!(%sender, change = %pending.description, "channel change confirmed");
+        ChannelsOutcome::Apply {
+          radio: pending.radio,
+          channels: pending.channels,
+          description: pending.description,
+          share_psk_with: pending.generated_psk.then_some(sender),
+        }
+      }
+      Some(pending) => {
+        let reply = "only whoever asked for the change can confirm it".to_string();
+        model.pending_channels = Some(pending);
+        ChannelsOutcome::Reply(reply)
+      }
+      None => ChannelsOutcome::Reply("nothing to confirm".to_string()),
+    },
+
+    ChannelCommand::Cancel => match model.pending_channels.take() {
+      Some(pending) => ChannelsOutcome::Reply(format!("cancelled: {}", pending.description)),
+      None => ChannelsOutcome::Reply("nothing to cancel".to_string()),
+    },
+
+    change => match plan(change, &model.radios[radio].channels) {
+      Ok(Plan {
+        channels,
+        mut description,
+        generated_psk,
+      }) => {
+        if config.radios.len() > 1 {
+          description.push_str(&format!(" on {}", model.radios[radio].name));
+        }
+        info!(%sender, change = %description, "channel change waiting on confirmation");
+        let mut reply = format!(
+          "about to {}\nsend /channels confirm within {} minutes to go ahead",
+          description,
+          CONFIRM_TIMEOUT.as_secs() / 60
+        );
+        if generated_psk {
+          reply.push_str(", the new psk will come to you in a 1:1 chat");
+        }
+        model.pending_channels = Some(PendingChannels {
+          radio,
+          requested_by: sender,
+          requested_at: Instant::now(),
+          channels,
+          description,
+          generated_psk,
+        });
+        ChannelsOutcome::Reply(reply)
+      }
+      Err(err) => ChannelsOutcome::Reply(err),
+    },
+  }
+}
+
+/// The psks of channels we just put on the radio, for a 1:1 chat with whoever confirmed it
+pub fn psk_report(channels: &ChannelTable, applied: &[Channel]) -> String {
+  let lines: Vec<String> = applied
+    .iter()
+    .filter_map(|channel| {
+      let settings = channel.settings.as_ref()?;
+      Some(format!(
+        "#{} {}: {}",
+        channel.index,
+        channels.display_name(settings),
+        BASE64_STANDARD.encode(&settings.psk)
+      ))
+    })
+    .collect();
+  format!(
+    "the new psk, for every node that should be on the channel. dont paste it in the group\n{}",
+    lines.join("\n")
+  )
+}
+
+/// The admin messages that make a change happen, wrapped in an edit so the radio only
+/// reboots once at the end
+pub fn channel_admin_messages(channels: &[Channel]) -> Vec<meshtastic::protobufs::AdminMessage> {
+  let admin = |variant| meshtastic::protobufs::AdminMessage {
+    payload_variant: Some(variant),
+    ..Default::default()
+  };
+
+  let mut messages = vec![admin(admin_message::PayloadVariant::BeginEditSettings(true))];
+  messages.extend(
+    channels
+      .iter()
+      .map(|channel| admin(admin_message::PayloadVariant::SetChannel(channel.clone()))),
+  );
+  messages.push(admin(admin_message::PayloadVariant::CommitEditSettings(true)));
+  messages
+}
@@ -0,0 +1,383 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::bail;
+use chrono::Utc;
+use meshtastic::Message;
+use meshtastic::protobufs::{FromRadio, PortNum, from_radio, mesh_packet};
+use meshtastic::utils::stream::StreamHandle;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+use tokio::task::spawn_local;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::config::RadioConfig;
+
+// every frame on the serial stream starts with these, followed by a big endian u16 length
+const START1: u8 = 0x94;
+const START2: u8 = 0xc3;
+/// the firmware caps packets at 512 bytes, anything bigger means we locked onto garbage
+const MAX_FRAME: usize = 512;
+
+/// Pulls the next complete frame out of `buffer`, skipping any junk before the start bytes
+pub fn next_frame(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+  loop {
+    let start = buffer.windows(2).position(|window| window == [START1, START2])?;
+    buffer.drain(..start);
+
+    if buffer.len() < 4 {
+      return None;
+    }
+
+    let length = u16::from_be_bytes([buffer[2], buffer[3]]) as usize;
+    if length > MAX_FRAME {
+      buffer.drain(..2);
+      continue;
+    }
+    if buffer.len() < 4 + length {
+      return None;
+    }
+
+    let frame = buffer[4..4 + length].to_vec();
+    buffer.drain(..4 + length);
+    return Some(frame);
+  }
+}
+
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+  let mut frame = Vec::with_capacity(payload.len() + 4);
+  frame.push(START1);
+  frame.push(START2);
+  frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+  frame.extend_from_slice(payload);
+  frame
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+  FromRadio,
+  ToRadio,
+}
+
+/// One frame off the wire. On disk its a direction byte, the unix millis as an i64, then the
+/// protobuf itself prefixed with its length as a u32, all big endian.
+#[derive(Debug, Clone)]
+pub struct Record {
+  pub direction: Direction,
+  pub timestamp_ms: i64,
+  pub frame: Vec<u8>,
+}
+
+impl Record {
+  fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(self.frame.len() + 13);
+    bytes.push(match self.direction {
+      Direction::FromRadio => 0,
+      Direction::ToRadio => 1,
+    });
+    bytes.extend_from_slice(&self.timestamp_ms.to_be_bytes());
+    bytes.extend_from_slice(&(self.frame.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&self.frame);
+    // one write per record so a crash leaves at most the last one half written
+    out.write_all(&bytes)
+  }
+
+  fn read_from(input: &mut impl Read) -> io::Result<Option<Self>> {
+    let mut direction = [0; 1];
+    match input.read_exact(&mut direction) {
+      Ok(()) => {}
+      Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+      Err(err) => return Err(err),
+    }
+
+    let mut timestamp = [0; 8];
+    input.read_exact(&mut timestamp)?;
+    let mut length = [0; 4];
+    input.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    // only whole frames get recorded, so this is a corrupt file and not worth allocating for
+    if length > MAX_FRAME {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("record claims a {} byte frame", length),
+      ));
+    }
+    let mut frame = vec![0; length];
+    input.read_exact(&mut frame)?;
+
+    Ok(Some(Self {
+      direction: match direction[0] {
+        0 => Direction::FromRadio,
+        _ => Direction::ToRadio,
+      },
+      timestamp_ms: i64::from_be_bytes(timestamp),
+      frame,
+    }))
+  }
+}
+
+pub fn read_capture(path: &str) -> anyhow::Result<Vec<Record>> {
+  let mut file = io::BufReader::new(File::open(path)?);
+  let mut records = vec![];
+
+  loop {
+    match Record::read_from(&mut file) {
+      Ok(Some(record)) => records.push(record),
+      Ok(None) => break,
+      // most likely we got killed mid write, everything before it is still good
+      Err(err) => {
+        warn!(%err, path, records = records.len(), "capture ends in a broken record");
+        break;
+      }
+    }
+  }
+
+  Ok(records)
+}
+
+/// Splits the raw bytes going each way back into frames and writes them to the capture file
+struct Recorder {
+  file: File,
+  from_radio: Vec<u8>,
+  to_radio: Vec<u8>,
+  broken: bool,
+}
+
+impl Recorder {
+  fn feed(&mut self, direction: Direction, bytes: &[u8]) {
+    let buffer = match direction {
+      Direction::FromRadio => &mut self.from_radio,
+      Direction::ToRadio => &mut self.to_radio,
+    };
+    buffer.extend_from_slice(bytes);
+
+    while let Some(frame) = next_frame(buffer) {
+      let record = Record {
+        direction,
+        timestamp_ms: Utc::now().timestamp_millis(),
+        frame,
+      };
+      if let Err(err) = record.write_to(&mut self.file) {
+        // dont spam the log for every packet, once is enough to know the capture is toast
+        if !self.broken {
+          warn!(%err, "failed to write capture, carrying on without it");
+          self.broken = true;
+        }
+      }
+    }
+  }
+}
+
+/// Passes everything through to the real stream untouched, keeping a copy of each frame
+pub struct CaptureStream<S> {
+  inner: S,
+  recorder: Recorder,
+}
+
+impl<S> CaptureStream<S> {
+  pub fn new(inner: S, path: &str) -> anyhow::Result<Self> {
+    let file = File::options().create(true).append(true).open(path)?;
+    info!(path, "capturing radio traffic");
+
+    Ok(Self {
+      inner,
+      recorder: Recorder {
+        file,
+        from_radio: vec![],
+        to_radio: vec![],
+        broken: false,
+      },
+    })
+  }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CaptureStream<S> {
+  fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    let before = buf.filled().len();
+    let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+    if let Poll::Ready(Ok(())) = &poll {
+      this.recorder.feed(Direction::FromRadio, &buf.filled()[before..]);
+    }
+    poll
+  }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CaptureStream<S> {
+  fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    let this = self.get_mut();
+    let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+    if let Poll::Ready(Ok(written)) = &poll {
+      this.recorder.feed(Direction::ToRadio, &buf[..*written]);
+    }
+    poll
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+  }
+
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+  }
+}
+
+/// Only mesh packets get filtered, the config stuff always goes through or the bridge
+/// wouldnt know its channels
+fn wanted(frame: &[u8], ports: &[PortNum]) -> bool {
+  if ports.is_empty() {
+    return true;
+  }
+
+  match FromRadio::decode(frame) {
+    Ok(FromRadio {
+      payload_variant: Some(from_radio::PayloadVariant::Packet(packet)),
+      ..
+    }) => match &packet.payload_variant {
+      Some(mesh_packet::PayloadVariant::Decoded(data)) => ports.contains(&data.portnum()),
+      _ => false,
+    },
+    _ => true,
+  }
+}
+
+/// Plays the radio side of a capture back over an in memory pipe with the original timing
+/// (scaled by `replay_speed`), the returned stream goes to `StreamApi::connect` like the
+/// serial one would. The pipe closes once its all played, which stops the bridge.
+pub fn spawn_replay(path: &str, config: &RadioConfig) -> anyhow::Result<StreamHandle<DuplexStream>> {
+  let mut ports = vec![];
+  for name in &config.replay_ports {
+    let Some(port) = PortNum::from_str_name(name) else {
+      bail!("unknown port number in replay_ports: {}", name);
+    };
+    ports.push(port);
+  }
+
+  let records: Vec<Record> = read_capture(path)?
+    .into_iter()
+    .filter(|record| record.direction == Direction::FromRadio && wanted(&record.frame, &ports))
+    .collect();
+  info!(
+    path,
+    frames = records.len(),
+    speed = config.replay_speed,
+    "replaying capture"
+  );
+
+  let speed = config.replay_speed;
+  let (bridge_end, radio_end) = tokio::io::duplex(64 * 1024);
+
+  spawn_local(async move {
+    let (mut reader, mut writer) = tokio::io::split(radio_end);
+
+    // nobody cares what the bridge says during a replay, but the pipe fills up if we dont read it
+    let sink = spawn_local(async move {
+      let mut sink = [0; 1024];
+      while let Ok(read) = reader.read(&mut sink).await {
+        if read == 0 {
+          break;
+        }
+      }
+    });
+
+    let mut previous = None;
+    for record in records {
+      if let Some(previous) = previous
+        && speed > 0.0
+      {
+        let gap = (record.timestamp_ms - previous).max(0) as f64 / 1000.0 / speed;
+        sleep(Duration::from_secs_f64(gap)).await;
+      }
+      previous = Some(record.timestamp_ms);
+
+      if writer.write_all(&encode_frame(&record.frame)).await.is_err() {
+        return;
+      }
+    }
+
+    // closing both ends of the pipe is how the bridge finds out theres nothing more coming
+    info!("replay finished");
+    sink.abort();
+  });
+
+  Ok(StreamHandle::from_stream(bridge_end))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn record(frame: Vec<u8>) -> Record {
+    Record {
+      direction: Direction::ToRadio,
+      timestamp_ms: 1_700_000_000_000,
+      frame,
+    }
+  }
+
+  #[test]
+  fn records_round_trip() {
+    let mut bytes = vec![];
+    record(vec![1, 2, 3]).write_to(&mut bytes).unwrap();
+    record(vec![0xaa; MAX_FRAME]).write_to(&mut bytes).unwrap();
+
+    let mut input = bytes.as_slice();
+    let first = Record::read_from(&mut input).unwrap().unwrap();
+    assert_eq!(first.direction, Direction::ToRadio);
+    assert_eq!(first.timestamp_ms, 1_700_000_000_000);
+    assert_eq!(first.frame, vec![1, 2, 3]);
+    assert_eq!(Record::read_from(&mut input).unwrap().unwrap().frame.len(), MAX_FRAME);
+    assert!(Record::read_from(&mut input).unwrap().is_none());
+  }
+
+  #[test]
+  fn oversized_length_is_rejected() {
+    let mut bytes = vec![0];
+    bytes.extend_from_slice(&0i64.to_be_bytes());
+    bytes.extend_from_slice(&u32::MAX.to_be_bytes());
+
+    let err = Record::read_from(&mut bytes.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+  }
+
+  #[tokio::test]
+  async fn replay_closes_when_its_done() {
+    let path = std::env::temp_dir().join(format!("replay-{}.capture", std::process::id()));
+    let mut file = File::create(&path).unwrap();
+    for frame in [vec![1, 2, 3], vec![4, 5]] {
+      Record {
+        direction: Direction::FromRadio,
+        timestamp_ms: 1_700_000_000_000,
+        frame,
+      }
+      .write_to(&mut file)
+      .unwrap();
+    }
+    drop(file);
+
+    let config = RadioConfig {
+      replay_speed: 0.0,
+      ..Default::default()
+    };
+    let played = tokio::task::LocalSet::new()
+      .run_until(async {
+        let mut stream = spawn_replay(path.to_str().unwrap(), &config).unwrap().stream;
+        let mut played = vec![];
+        // reading to the end only finishes if the replay let go of the pipe
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut played))
+          .await
+          .expect("replay never closed the pipe")
+          .unwrap();
+        played
+      })
+      .await;
+    std::fs::remove_file(&path).unwrap();
+
+    let mut expected = encode_frame(&[1, 2, 3]);
+    expected.extend(encode_frame(&[4, 5]));
+    assert_eq!(played, expected);
+  }
+}
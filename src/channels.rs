@@ -0,0 +1,119 @@
+use meshtastic::protobufs::{Channel, ChannelSettings, channel::Role};
+use tracing::warn;
+
+use crate::config::{Config, RadioConfig};
+
+/// Every radio has exactly this many channel slots, numbered the same way packets refer to them
+pub const CHANNEL_SLOTS: usize = 8;
+
+// the radio names an unnamed channel after its modem preset, this is the one everyone uses
+const DEFAULT_PRESET_NAME: &str = "LongFast";
+
+/// The radios channels by slot. Packets only carry the slot number, so a disabled slot has to
+/// leave a hole instead of shifting everything after it down.
+#[derive(Debug, Default)]
+pub struct ChannelTable {
+  slots: [Option<Channel>; CHANNEL_SLOTS],
+  /// from the lora config, which comes after the channels
+  preset_name: Option<String>,
+}
+
+impl ChannelTable {
+  pub fn clear(&mut self) {
+    self.slots = Default::default();
+    self.preset_name = None;
+  }
+
+  pub fn set_preset_name(&mut self, preset_name: Option<String>) {
+    self.preset_name = preset_name;
+  }
+
+  /// What the apps call a channel, which for the usual unnamed primary is its preset
+  pub fn display_name<'a>(&'a self, settings: &'a ChannelSettings) -> &'a str {
+    if settings.name.is_empty() {
+      self.preset_name.as_deref().unwrap_or(DEFAULT_PRESET_NAME)
+    } else {
+      &settings.name
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.slots.iter().all(Option::is_none)
+  }
+
+  /// Puts a channel in its slot, returns whether that actually changed anything so a live edit
+  /// can be told apart from the radio repeating itself
+  pub fn update(&mut self, channel: Channel) -> bool {
+    let Some(slot) = usize::try_from(channel.index).ok().filter(|slot| *slot < CHANNEL_SLOTS) else {
+      warn!(index = channel.index, "channel slot out of range");
+      return false;
+    };
+
+    let channel = (channel.role() != Role::Disabled).then_some(channel);
+    if self.slots[slot] == channel {
+      return false;
+    }
+    self.slots[slot] = channel;
+    true
+  }
+
+  pub fn get(&self, index: u32) -> Option<&Channel> {
+    self.slots.get(index as usize)?.as_ref()
+  }
+
+  pub fn settings(&self, index: u32) -> Option<&ChannelSettings> {
+    self.get(index)?.settings.as_ref()
+  }
+
+  pub fn name(&self, index: u32) -> Option<&str> {
+    self.settings(index).map(|settings| self.display_name(settings))
+  }
+
+  /// Enabled channels along with their slot
+  pub fn iter(&self) -> impl Iterator<Item = (u32, &Channel)> {
+    self
+      .slots
+      .iter()
+      .enumerate()
+      .filter_map(|(index, channel)| Some((index as u32, channel.as_ref()?)))
+  }
+
+  /// Slot of the channel with this name, ignoring case like the apps do
+  pub fn find(&self, name: &str) -> Option<u32> {
+    self
+      .iter()
+      .find(|(_, channel)| {
+        channel
+          .settings
+          .as_ref()
+          .is_some_and(|settings| self.display_name(settings).eq_ignore_ascii_case(name))
+      })
+      .map(|(index, _)| index)
+  }
+
+  /// The slot the bridge is on, by name if the config gives one so it follows the channel
+  /// around. Before the radio has told us anything the config is all we have to go on.
+  pub fn bridged(&self, config: &Config, radio: &RadioConfig) -> Option<u32> {
+    let (index, name) = bridge_setting(config, radio);
+    if self.is_empty() {
+      return Some(index as u32);
+    }
+
+    match name {
+      Some(name) => self.find(name),
+      None => self.get(index as u32).map(|_| index as u32),
+    }
+  }
+}
+
+/// Which channel a radio bridges. Its own setting wins, otherwise its the top level one
+pub fn bridge_setting<'a>(config: &'a Config, radio: &'a RadioConfig) -> (usize, Option<&'a str>) {
+  if radio.channel_index.is_some() || radio.channel_name.is_some() {
+    (
+      radio.channel_index.unwrap_or(config.channel_index),
+      radio.channel_name.as_deref(),
+    )
+  } else {
+    (config.channel_index, config.channel_name.as_deref())
+  }
+}
@@ -0,0 +1,458 @@
+use std::fs::File;
+use std::io::prelude::*;
+
+use presage::libsignal_service::zkgroup::GroupMasterKeyBytes;
+use serde::{Deserialize, Serialize};
+
+use crate::meshy::parse_node_id;
+use crate::signal::config_dir_path;
+
+#[derive(Deserialize, Serialize)]
+struct RawConfig {
+  group_key: String,
+  #[serde(default = "default_channel_index")]
+  channel_index: usize,
+  #[serde(default)]
+  channel_name: Option<String>,
+  #[serde(default)]
+  store_forward: StoreForwardConfig,
+  /// the old single radio table, still works when theres only one
+  #[serde(default)]
+  radio: Option<RadioConfig>,
+  #[serde(default)]
+  radios: Vec<RadioConfig>,
+  #[serde(default = "default_shutdown_timeout")]
+  shutdown_timeout_secs: u64,
+  #[serde(default)]
+  logging: LoggingConfig,
+  #[serde(default)]
+  metrics: MetricsConfig,
+  #[serde(default)]
+  signal: SignalConfig,
+  #[serde(default)]
+  mesh: MeshConfig,
+  #[serde(default)]
+  templates: TemplatesConfig,
+  #[serde(default)]
+  shaping: ShapingConfig,
+  #[serde(default)]
+  group_notices: GroupNoticesConfig,
+  #[serde(default)]
+  node_notices: NodeNoticesConfig,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Config {
+  pub group_key: GroupMasterKeyBytes,
+  pub channel_index: usize,
+  /// bridge the channel with this name instead, wherever it is on the radio
+  pub channel_name: Option<String>,
+  pub store_forward: StoreForwardConfig,
+  /// every radio we drive, `[radio]` from older configs ends up first in here
+  pub radios: Vec<RadioConfig>,
+  /// how long we wait on acks and signal sends before giving up on a clean exit
+  pub shutdown_timeout_secs: u64,
+  pub logging: LoggingConfig,
+  pub metrics: MetricsConfig,
+  pub signal: SignalConfig,
+  pub mesh: MeshConfig,
+  pub templates: TemplatesConfig,
+  pub shaping: ShapingConfig,
+  pub group_notices: GroupNoticesConfig,
+  pub node_notices: NodeNoticesConfig,
+}
+
+fn default_channel_index() -> usize {
+  1
+}
+
+fn default_shutdown_timeout() -> u64 {
+  10
+}
+
+/// How long signal messages are allowed to wait around for the radio to come back
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StoreForwardConfig {
+  /// messages older than this get dropped instead of flushed to the mesh
+  pub max_age_minutes: u64,
+  /// oldest messages get dropped once the queue is this long
+  pub max_messages: usize,
+}
+
+impl Default for StoreForwardConfig {
+  fn default() -> Self {
+    Self {
+      max_age_minutes: 60,
+      max_messages: 100,
+    }
+  }
+}
+
+/// Where the radio lives and how hard we try to get it back when it goes away
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RadioConfig {
+  /// what the radio is called in logs, notices and commands. has to be unique with more than one
+  pub name: String,
+  /// serial port the radio is plugged into
+  pub port: String,
+  /// the radio counts as stalled if we hear nothing from it for this long
+  pub heartbeat_secs: u64,
+  pub min_backoff_secs: u64,
+  pub max_backoff_secs: u64,
+  /// ask the radio for its channels this often, to notice changes someone made from the app.
+  /// 0 turns it off
+  pub channel_refresh_secs: u64,
+  /// bridge this channel on this radio instead of the top level `channel_index`
+  pub channel_index: Option<usize>,
+  /// same but by name, like the top level `channel_name`
+  pub channel_name: Option<String>,
+  /// also send whatever this radio bridges to a channel on another radio
+  pub relay_to: Option<RelayConfig>,
+  /// append every frame to and from the radio to this file, for reproducing bugs later
+  pub capture: Option<String>,
+  /// play this capture back instead of talking to a radio at all. it plays once, the bridge
+  /// stops when its done, and whatever text is in it really does get bridged to signal
+  pub replay: Option<String>,
+  /// 2.0 plays the capture back twice as fast, 0 as fast as possible
+  pub replay_speed: f64,
+  /// only replay mesh packets on these ports, ie. ["TEXT_MESSAGE_APP"]. empty means all of them
+  pub replay_ports: Vec<String>,
+}
+
+impl Default for RadioConfig {
+  fn default() -> Self {
+    Self {
+      name: "radio".to_string(),
+      port: "/dev/ttyACM0".to_string(),
+      heartbeat_secs: 900,
+      min_backoff_secs: 1,
+      max_backoff_secs: 300,
+      channel_refresh_secs: 10 * 60,
+      channel_index: None,
+      channel_name: None,
+      relay_to: None,
+      capture: None,
+      replay: None,
+      replay_speed: 1.0,
+      replay_ports: vec![],
+    }
+  }
+}
+
+/// Where messages from one radio get relayed to on another
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RelayConfig {
+  /// `name` of the other radio
+  pub radio: String,
+  /// channel on that radio by name, its bridged channel if not set
+  #[serde(default)]
+  pub channel: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+  /// env-filter directive, ie. "info" or "meshtastic_2_signal=debug,presage=warn".
+  /// `RUST_LOG` takes priority over this if its set
+  pub level: String,
+  /// log to this file too, rotated according to `rotation`
+  pub file: Option<String>,
+  /// one of "minutely", "hourly", "daily" or "never"
+  pub rotation: String,
+  /// one json object per line instead of the human friendly format
+  pub json: bool,
+}
+
+impl Default for LoggingConfig {
+  fn default() -> Self {
+    Self {
+      level: "info".to_string(),
+      file: None,
+      rotation: "daily".to_string(),
+      json: false,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+  /// address to serve prometheus metrics on, ie. "127.0.0.1:9464". off if not set
+  pub listen: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SignalConfig {
+  /// how long a looked up sender name is good for before we fetch their profile again
+  pub name_ttl_secs: u64,
+}
+
+impl Default for SignalConfig {
+  fn default() -> Self {
+    Self { name_ttl_secs: 60 * 60 }
+  }
+}
+
+/// Which of a nodes names we put in front of its messages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NameDisplay {
+  #[default]
+  Long,
+  Short,
+  Both,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MeshConfig {
+  pub name_display: NameDisplay,
+  /// dont ask the same unknown node who it is more often than this
+  pub nodeinfo_request_secs: u64,
+  /// and dont ask more than this many unknown nodes a minute altogether, so a busy mesh full of
+  /// strangers doesnt have us flooding it. 0 stops asking at all
+  pub nodeinfo_requests_per_minute: usize,
+  /// bridged channels (by name) that get long signal messages unishox2 compressed, when that
+  /// saves splitting them up. the stock apps and most firmware dont show
+  /// TEXT_MESSAGE_COMPRESSED_APP at all, so only list a channel if every node on it can
+  pub compress_channels: Vec<String>,
+  /// same for `/dm`s, by node id like "!a1b2c3d4"
+  pub compress_nodes: Vec<String>,
+}
+
+impl MeshConfig {
+  /// Whether the config says whoever gets this can read compressed text
+  pub fn compresses_for(&self, channel: Option<&str>, node: Option<u32>) -> bool {
+    match node {
+      Some(node) => self.compress_nodes.iter().any(|id| parse_node_id(id) == Some(node)),
+      None => channel.is_some_and(|channel| self.compress_channels.iter().any(|name| name == channel)),
+    }
+  }
+}
+
+impl Default for MeshConfig {
+  fn default() -> Self {
+    Self {
+      name_display: NameDisplay::Long,
+      nodeinfo_request_secs: 10 * 60,
+      nodeinfo_requests_per_minute: 6,
+      compress_channels: vec![],
+      compress_nodes: vec![],
+    }
+  }
+}
+
+/// Telling the mesh when people join or leave the signal group, so the field knows whos listening
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GroupNoticesConfig {
+  pub enabled: bool,
+  /// at most one notice this often, anything in between gets rolled into the next one
+  pub min_interval_secs: u64,
+}
+
+impl Default for GroupNoticesConfig {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      min_interval_secs: 5 * 60,
+    }
+  }
+}
+
+/// Telling the signal group when mesh nodes show up, come back or go quiet
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct NodeNoticesConfig {
+  pub enabled: bool,
+  /// a node heard again after being silent this long counts as back
+  pub back_after_minutes: u64,
+  /// a node silent this long counts as gone quiet, 0 to never say so
+  pub quiet_after_minutes: u64,
+  /// `!nodeid`s that never get announced. nodes can also dm the gateway "/announce off"
+  pub ignore: Vec<String>,
+}
+
+impl Default for NodeNoticesConfig {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      back_after_minutes: 6 * 60,
+      quiet_after_minutes: 3 * 60,
+      ignore: vec![],
+    }
+  }
+}
+
+/// How bridged messages get formatted on the other side. Placeholders are {name}, {short},
+/// {id}, {channel}, {radio}, {hops}, {snr}, {time} and {body}, ie. "[{short}] {body}" for
+/// compact ones
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TemplatesConfig {
+  pub mesh_to_signal: String,
+  pub signal_to_mesh: String,
+  /// mesh messages one radio relays to another, see `relay_to`
+  pub relay: String,
+}
+
+impl Default for TemplatesConfig {
+  fn default() -> Self {
+    Self {
+      mesh_to_signal: "{name}:\n{body}".to_string(),
+      signal_to_mesh: "{name}:\n{body}".to_string(),
+      relay: "[{radio}] {name}: {body}".to_string(),
+    }
+  }
+}
+
+/// What to do with characters outside the basic multilingual plane, which is mostly emoji
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmojiMode {
+  /// current apps show them fine, theyre just 4 bytes each
+  #[default]
+  Keep,
+  /// common ones become ascii like ":)", the rest become `emoji_replacement`
+  Fold,
+  Strip,
+}
+
+/// How signal text gets squeezed into mesh payloads
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ShapingConfig {
+  /// utf-8 bytes per mesh packet, longer messages get split up
+  pub max_bytes: usize,
+  pub emoji: EmojiMode,
+  /// what `fold` turns emoji without an ascii version into. empty drops them without a trace
+  pub emoji_replacement: String,
+  pub strip_zero_width: bool,
+  pub collapse_whitespace: bool,
+}
+
+impl Default for ShapingConfig {
+  fn default() -> Self {
+    Self {
+      // what the official apps let you type
+      max_bytes: 200,
+      emoji: EmojiMode::Keep,
+      emoji_replacement: "?".to_string(),
+      strip_zero_width: true,
+      collapse_whitespace: true,
+    }
+  }
+}
+
+impl From<RawConfig> for Config {
+  fn from(value: RawConfig) -> Self {
+    let almost_key = hex::decode(value.group_key).expect("failed to parse key\nshould parese to a [u8; 32]");
+    if almost_key.len() != 32 {
+      panic!("incorrect key length: {}", almost_key.len());
+    }
+    let mut key: [u8; 32] = [0; 32];
+    for (index, byte) in almost_key.iter().enumerate() {
+      key[index] = *byte;
+    }
+    // let key: GroupMasterKeyBytes = key;
+
+    let mut radios: Vec<RadioConfig> = value.radio.into_iter().chain(value.radios).collect();
+    if radios.is_empty() {
+      radios.push(RadioConfig::default());
+    }
+    for (index, radio) in radios.iter().enumerate() {
+      if radios[..index].iter().any(|other| other.name == radio.name) {
+        panic!("theres more than one radio called {}", radio.name);
+      }
+      if let Some(relay) = &radio.relay_to {
+        if relay.radio == radio.name || !radios.iter().any(|other| other.name == relay.radio) {
+          panic!(
+            "radio {} relays to {}, which isnt another radio",
+            radio.name, relay.radio
+          );
+        }
+      }
+    }
+
+    for id in &value.node_notices.ignore {
+      if parse_node_id(id).is_none() {
+        panic!("{} in node_notices.ignore isnt a !nodeid", id);
+      }
+    }
+
+    Config {
+      group_key: key,
+      channel_index: value.channel_index,
+      channel_name: value.channel_name,
+      store_forward: value.store_forward,
+      radios,
+      shutdown_timeout_secs: value.shutdown_timeout_secs,
+      logging: value.logging,
+      metrics: value.metrics,
+      signal: value.signal,
+      mesh: value.mesh,
+      templates: value.templates,
+      shaping: value.shaping,
+      group_notices: value.group_notices,
+      node_notices: value.node_notices,
+    }
+  }
+}
+
+/// Everything at its defaults, with a made up group key
+#[cfg(test)]
+pub fn test_config() -> Config {
+  let raw: RawConfig = toml::from_str(&format!("group_key = \"{}\"", "11".repeat(32))).unwrap();
+  raw.into()
+}
+
+fn config_path() -> String {
+  let mut dir = config_dir_path();
+  dir.push_str("config.toml");
+  dir
+}
+
+/// A fresh install doesnt have one until someone writes it with a group key from the listing
+pub fn config_exists() -> bool {
+  std::path::Path::new(&config_path()).exists()
+}
+
+pub fn parse_config() -> Config {
+  let mut file = match File::open(config_path()) {
+    Ok(f) => f,
+    Err(err) => {
+      eprintln!("unable to open file 'config.toml'");
+      eprintln!("heres an error also {}", err);
+      panic!();
+    }
+  };
+  let mut contents = String::new();
+  file.read_to_string(&mut contents).expect("cmon no way this fails");
+
+  let raw: RawConfig = toml::from_str(&contents).expect("failed to parse config file");
+  raw.into()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn compression_is_opt_in_per_destination() {
+    let mut mesh = MeshConfig::default();
+    assert!(!mesh.compresses_for(Some("gateway"), None));
+    assert!(!mesh.compresses_for(Some("gateway"), Some(0xa11c)));
+
+    mesh.compress_channels = vec!["gateway".to_string()];
+    mesh.compress_nodes = vec!["!0000a11c".to_string()];
+    assert!(mesh.compresses_for(Some("gateway"), None));
+    assert!(!mesh.compresses_for(Some("LongFast"), None));
+    assert!(!mesh.compresses_for(None, None));
+    // a dm goes by who its for, whatever channel it rides on
+    assert!(mesh.compresses_for(Some("LongFast"), Some(0xa11c)));
+    assert!(!mesh.compresses_for(Some("gateway"), Some(0xb0b)));
+  }
+}
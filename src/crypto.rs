@@ -0,0 +1,164 @@
+use aes::{Aes128, Aes256};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use meshtastic::Message;
+use meshtastic::protobufs::{ChannelSettings, Data};
+
+use crate::channels::ChannelTable;
+
+/// The well known key every radio ships with, a one byte psk picks a variation of it
+const DEFAULT_PSK: [u8; 16] = [
+  0xd4, 0xf1, 0xbb, 0x3a, 0x20, 0x29, 0x07, 0x59, 0xf0, 0xbc, 0xff, 0xab, 0xcf, 0x4e, 0x69, 0x01,
+];
+
+/// The actual aes key for a channel psk, `None` for channels that arent encrypted at all
+pub fn expand_psk(psk: &[u8]) -> Option<Vec<u8>> {
+  match psk.len() {
+    0 => None,
+    1 if psk[0] == 0 => None,
+    1 => {
+      let mut key = DEFAULT_PSK.to_vec();
+      key[15] = key[15].wrapping_add(psk[0] - 1);
+      Some(key)
+    }
+    // short keys get zero padded the same way the firmware does it
+    2..=16 => {
+      let mut key = psk.to_vec();
+      key.resize(16, 0);
+      Some(key)
+    }
+    _ => {
+      let mut key = psk.to_vec();
+      key.resize(32, 0);
+      Some(key)
+    }
+  }
+}
+
+fn xor_hash(bytes: &[u8]) -> u8 {
+  bytes.iter().fold(0, |hash, byte| hash ^ byte)
+}
+
+/// Encrypted packets only carry this one byte hash instead of the channel index
+pub fn channel_hash(name: &str, settings: &ChannelSettings) -> u8 {
+  let key = expand_psk(&settings.psk).unwrap_or_default();
+  xor_hash(name.as_bytes()) ^ xor_hash(&key)
+}
+
+/// AES-CTR with the packet id and sender as the nonce, same in both directions
+pub fn decrypt(key: &[u8], packet_id: u32, from: u32, encrypted: &[u8]) -> Option<Vec<u8>> {
+  let mut nonce = [0u8; 16];
+  nonce[..8].copy_from_slice(&(packet_id as u64).to_le_bytes());
+  nonce[8..12].copy_from_slice(&from.to_le_bytes());
+
+  let mut buf = encrypted.to_vec();
+  match key.len() {
+    16 => ctr::Ctr128BE::<Aes128>::new(key.into(), &nonce.into()).apply_keystream(&mut buf),
+    32 => ctr::Ctr128BE::<Aes256>::new(key.into(), &nonce.into()).apply_keystream(&mut buf),
+    _ => return None,
+  }
+  Some(buf)
+}
+
+/// Tries every channel whose hash matches, since the hash alone can collide. Returns the
+/// index of the channel it decrypted with along with the payload.
+pub fn try_decrypt(
+  channels: &ChannelTable,
+  hash: u32,
+  packet_id: u32,
+  from: u32,
+  encrypted: &[u8],
+) -> Option<(u32, Data)> {
+  channels.iter().find_map(|(index, channel)| {
+    let settings = channel.settings.as_ref()?;
+    if channel_hash(channels.display_name(settings), settings) as u32 != hash {
+      return None;
+    }
+    let key = expand_psk(&settings.psk)?;
+    let decrypted = decrypt(&key, packet_id, from, encrypted)?;
+    // a wrong key still "decrypts", it just wont parse (usually)
+    let data = Data::decode(decrypted.as_slice()).ok()?;
+    (data.portnum != 0).then_some((index, data))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use meshtastic::protobufs::{PortNum, channel};
+
+  use super::*;
+  use crate::mock_radio::mock_channel;
+
+  // "hello mesh" as TEXT_MESSAGE_APP, packet 0x12345678 from !0000a11c, under the default key
+  const HELLO: [u8; 14] = [
+    0xcb, 0x01, 0xe3, 0x6d, 0x14, 0xdb, 0x7c, 0x64, 0x60, 0x33, 0x3a, 0x43, 0x8a, 0xe1,
+  ];
+  // more than one aes block, packet 0xdeadbeef from !433d2a1c
+  const LONGER: [u8; 34] = [
+    0xbb, 0x40, 0xe7, 0xf9, 0x06, 0x29, 0x18, 0xee, 0x57, 0x19, 0x11, 0xc9, 0x9a, 0x46, 0xa6, 0xe7, 0x5c, 0x68, 0x14,
+    0x06, 0x24, 0x40, 0x11, 0x1e, 0x23, 0x4c, 0x2d, 0xcc, 0x1a, 0x5c, 0x10, 0xc4, 0xe9, 0xb7,
+  ];
+
+  fn settings(name: &str, psk: Vec<u8>) -> ChannelSettings {
+    ChannelSettings {
+      name: name.to_string(),
+      psk,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn psk_expansion() {
+    assert_eq!(expand_psk(&[]), None);
+    assert_eq!(expand_psk(&[0]), None);
+    assert_eq!(expand_psk(&[1]), Some(DEFAULT_PSK.to_vec()));
+
+    let mut second = DEFAULT_PSK.to_vec();
+    second[15] += 1;
+    assert_eq!(expand_psk(&[2]), Some(second));
+
+    assert_eq!(expand_psk(&[7, 7]).unwrap(), [&[7, 7][..], &[0; 14]].concat());
+    assert_eq!(expand_psk(&[9; 20]).unwrap(), [&[9; 20][..], &[0; 12]].concat());
+  }
+
+  #[test]
+  fn longfast_hash() {
+    assert_eq!(channel_hash("LongFast", &settings("", vec![1])), 8);
+    // no encryption hashes the name alone
+    assert_eq!(channel_hash("ab", &settings("", vec![0])), b'a' ^ b'b');
+  }
+
+  #[test]
+  fn decrypts_default_key_packets() {
+    let key = expand_psk(&[1]).unwrap();
+    let data = Data::decode(decrypt(&key, 0x12345678, 0xa11c, &HELLO).unwrap().as_slice()).unwrap();
+    assert_eq!(data.portnum(), PortNum::TextMessageApp);
+    assert_eq!(data.payload, b"hello mesh");
+
+    let data = Data::decode(decrypt(&key, 0xdeadbeef, 0x433d2a1c, &LONGER).unwrap().as_slice()).unwrap();
+    assert_eq!(data.payload, b"testing the counter, 2 blocks!");
+
+    assert_eq!(decrypt(&[0; 5], 1, 1, &HELLO), None);
+  }
+
+  #[test]
+  fn finds_the_channel_by_hash() {
+    let mut channels = ChannelTable::default();
+    // the unnamed primary goes by its preset
+    channels.set_preset_name(Some("LongFast".to_string()));
+    channels.update(mock_channel(0, "", channel::Role::Primary));
+    let mut private = mock_channel(1, "private", channel::Role::Secondary);
+    private.settings.as_mut().unwrap().psk = vec![0x42; 16];
+    channels.update(private);
+
+    let (index, data) = try_decrypt(&channels, 8, 0x12345678, 0xa11c, &HELLO).unwrap();
+    assert_eq!(index, 0);
+    assert_eq!(data.payload, b"hello mesh");
+
+    // right hash, wrong sender, so the nonce is off and it doesnt parse as anything
+    assert!(try_decrypt(&channels, 8, 0x12345678, 0xa11d, &HELLO).is_none());
+    // only the private channel has this hash, and its key turns the packet into garbage
+    let private_hash = channel_hash("private", &settings("private", vec![0x42; 16])) as u32;
+    assert_eq!(private_hash, 109);
+    assert!(try_decrypt(&channels, private_hash, 0x12345678, 0xa11c, &HELLO).is_none());
+  }
+}
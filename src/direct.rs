@@ -0,0 +1,132 @@
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use meshtastic::packet::PacketDestination;
+use tracing::info;
+
+use crate::config::Config;
+use crate::meshy::parse_node_id;
+use crate::metrics::{METRICS, inc};
+use crate::shaping::shape;
+use crate::status::node_display_name;
+use crate::template::{TemplateVars, render};
+use crate::update::{Action, reply_to_thread};
+use crate::{Model, SignalMessage, Thread, Uuid};
+
+/// How long a node answering gets its reply passed to whoever `/dm`ed it
+pub const DM_THREAD_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Which radio hears a node, by `!nodeid`, short name or long name. Names are compared
+/// without caring about case, and more than one node going by it is an error listing them
+fn find_target(model: &Model, query: &str) -> Result<(usize, u32), String> {
+  if let Some(node) = parse_node_id(query) {
+    return model
+      .radios
+      .iter()
+      .position(|state| state.nodes.contains_key(&node))
+      .map(|radio| (radio, node))
+      .ok_or_else(|| format!("havent heard from !{:08x} on any radio", node));
+  }
+
+  let wanted = query.to_lowercase();
+  let mut found: Vec<(usize, u32)> = vec![];
+  for (radio, state) in model.radios.iter().enumerate() {
+    for (node, info) in &state.nodes {
+      let Some(user) = &info.user else {
+        continue;
+      };
+      let matches = user.short_name.to_lowercase() == wanted || user.long_name.to_lowercase() == wanted;
+      // two radios can both hear the same node, the first one will do
+      if matches && !found.iter().any(|(_, seen)| seen == node) {
+        found.push((radio, *node));
+      }
+    }
+  }
+
+  match found.as_slice() {
+    [] => Err(format!("no node goes by {}", query)),
+    [target] => Ok(*target),
+    _ => {
+      let mut options: Vec<String> = found
+        .iter()
+        .map(|(radio, node)| node_display_name(&model.radios[*radio].nodes, *node))
+        .collect();
+      options.sort();
+      Err(format!(
+        "more than one node goes by {}, use the id instead: {}",
+        query,
+        options.join(", ")
+      ))
+    }
+  }
+}
+
+/// `/dm <!nodeid|shortname|longname> <text>` from signal. Goes to just that node with an ack, and
+/// whatever it says back to the gateway goes to whoever sent it
+pub fn signal_dm(
+  model: &mut Model,
+  config: &Config,
+  thread: &Thread,
+  sender: Uuid,
+  timestamp: u64,
+  args: &str,
+) -> Action {
+  let Some((target, text)) = args.split_once(char::is_whitespace) else {
+    return reply_to_thread(
+      config,
+      thread,
+      "usage: /dm <!nodeid|shortname|longname> <message>".to_string(),
+    );
+  };
+  let (radio, node) = match find_target(model, target) {
+    Ok(found) => found,
+    Err(err) => return reply_to_thread(config, thread, err),
+  };
+  let text = text.trim();
+
+  let name = shape(&model.names.get(&sender), &config.shaping);
+  let short: String = name.chars().take(4).collect();
+  let id = sender.simple().to_string();
+  let time = Local::now().format("%H:%M").to_string();
+  let shaped = shape(text, &config.shaping);
+  let state = &model.radios[radio];
+  let body = render(
+    &config.templates.signal_to_mesh,
+    &TemplateVars {
+      name: &name,
+      short: &short,
+      id: &id[..8],
+      radio: &state.name,
+      time: &time,
+      body: &shaped,
+      ..Default::default()
+    },
+  )
+  .text;
+
+  info!(%sender, node, radio = %state.name, "sending signal dm to mesh node");
+  inc(&METRICS.signal_to_mesh);
+  // replies go to whoever dmed the node last
+  model
+    .dm_threads
+    .retain(|_, (_, sent_at)| sent_at.elapsed() < DM_THREAD_TIMEOUT);
+  model.dm_threads.insert(node, (sender, Instant::now()));
+
+  Action::SendToMesh {
+    radio,
+    body,
+    channel: 0.into(),
+    destination: PacketDestination::Node(node.into()),
+    signal_message: Some(SignalMessage {
+      body: text.to_string(),
+      sender,
+      timestamp,
+      contact: match thread {
+        Thread::Contact(uuid) => Some(*uuid),
+        Thread::Group(_) => None,
+      },
+      direct: true,
+    }),
+    outbox_id: None,
+  }
+}
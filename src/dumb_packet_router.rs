@@ -1,16 +1,19 @@
 use std::collections::HashMap;
 
 use meshtastic::{
+  Message,
   packet::PacketRouter,
   protobufs::{
-    self, MeshPacket, PortNum,
+    self, MeshPacket, PortNum, Routing,
     from_radio::{self, PayloadVariant},
-    mesh_packet,
+    mesh_packet, routing,
   },
   types::NodeId,
 };
 use tokio::sync::mpsc;
+use tracing::{debug, warn};
 
+use crate::metrics::{METRICS, inc};
 use crate::update::Action;
 
 // pub enum AckStatus {
@@ -20,21 +23,38 @@ use crate::update::Action;
 // }
 
 pub struct DumbPacketRouter {
-  id: NodeId,
+  /// our own node, which we only find out once the radio sends its MyInfo
+  id: Option<NodeId>,
   want_ack_packets: HashMap<u32, MeshPacket>,
   ack_notifs: tokio::sync::mpsc::UnboundedSender<Action>,
   heres_your_id: mpsc::UnboundedSender<u32>,
 }
 
 impl DumbPacketRouter {
-  pub fn new(id: NodeId, ack_notifs: mpsc::UnboundedSender<Action>, id_sender: mpsc::UnboundedSender<u32>) -> Self {
+  pub fn new(ack_notifs: mpsc::UnboundedSender<Action>, id_sender: mpsc::UnboundedSender<u32>) -> Self {
     Self {
-      id,
+      id: None,
       want_ack_packets: HashMap::new(),
       ack_notifs,
       heres_your_id: id_sender,
     }
   }
+
+  pub fn set_id(&mut self, id: NodeId) {
+    self.id = Some(id);
+  }
+
+  /// Start watching for an ack we didnt send this run (ie. one saved before a restart)
+  pub fn expect_ack(&mut self, id: u32) {
+    self.want_ack_packets.insert(
+      id,
+      MeshPacket {
+        id,
+        want_ack: true,
+        ..Default::default()
+      },
+    );
+  }
 }
 
 #[derive(Debug)]
@@ -62,10 +82,31 @@ impl PacketRouter<String, MyError> for DumbPacketRouter {
       if decoded.portnum == Into::<i32>::into(PortNum::RoutingApp) {
         if decoded.request_id != 0 {
           if let Some(packet) = self.want_ack_packets.remove(&decoded.request_id) {
-            self.ack_notifs.send(Action::MeshAck { packet, deliverd: true });
+            // an "ack" can also be the radio telling us it gave up, the reason tells them apart
+            let reason = match Routing::decode(decoded.payload.as_slice()) {
+              Ok(Routing {
+                variant: Some(routing::Variant::ErrorReason(reason)),
+              }) => routing::Error::try_from(reason).unwrap_or(routing::Error::None),
+              _ => routing::Error::None,
+            };
+            let deliverd = reason == routing::Error::None;
+
+            if deliverd {
+              inc(&METRICS.acks);
+            } else {
+              METRICS.routing_error(reason.as_str_name());
+            }
+
+            debug!(
+              packet_id = packet.id,
+              deliverd,
+              reason = reason.as_str_name(),
+              "ack matched a packet we sent"
+            );
+            self.ack_notifs.send(Action::MeshAck { packet, deliverd });
           }
         } else {
-          println!("dont th9ink this should happen");
+          warn!("routing packet without a request id");
         }
       }
     }
@@ -74,8 +115,12 @@ impl PacketRouter<String, MyError> for DumbPacketRouter {
   }
 
   fn handle_mesh_packet(&mut self, packet: meshtastic::protobufs::MeshPacket) -> Result<String, MyError> {
-    println!("not rly handling packet ngl");
-    // println!("here it is anyway: {:?}", packet);
+    debug!(
+      packet_id = packet.id,
+      to = packet.to,
+      want_ack = packet.want_ack,
+      "sent mesh packet"
+    );
 
     self.heres_your_id.send(packet.id);
 
@@ -87,7 +132,7 @@ impl PacketRouter<String, MyError> for DumbPacketRouter {
   }
 
   fn source_node_id(&self) -> meshtastic::types::NodeId {
-    self.id
-    // NodeId::new(2454871382)
+    // nothing gets sent before main knows the id, so this should never actually be 0
+    self.id.unwrap_or_else(|| NodeId::new(0))
   }
 }
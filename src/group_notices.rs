@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use meshtastic::packet::PacketDestination;
+use presage::libsignal_service::content::{Content, ContentBody, GroupContextV2};
+use presage::model::groups::Group;
+use presage::proto::SyncMessage;
+use presage::proto::sync_message::Sent;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+use crate::shaping::shape;
+use crate::update::Action;
+use crate::{Model, Uuid};
+
+/// Something that happened to the bridged group that the mesh might want to know about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupEvent {
+  Joined(Uuid),
+  Left(Uuid),
+  /// someone else took them out
+  Removed(Uuid),
+  Renamed(String),
+}
+
+/// Notices kept word for word while rate limited, anything past this is only counted
+const MAX_PENDING: usize = 5;
+
+/// Notices waiting for the rate limit, sent together once its up
+#[derive(Debug, Default)]
+pub struct GroupNotices {
+  pending: Vec<String>,
+  /// notices that came in with `pending` already full
+  overflow: usize,
+  last_sent: Option<Instant>,
+}
+
+impl GroupNotices {
+  pub fn push(&mut self, notice: String) {
+    if self.pending.len() >= MAX_PENDING {
+      debug!(%notice, "too many group notices queued, only counting this one");
+      self.overflow += 1;
+      return;
+    }
+    debug!(%notice, "queued group notice");
+    self.pending.push(notice);
+  }
+
+  /// When whatever is pending can go out, `None` when nothing is
+  pub fn next_deadline(&self, config: &Config) -> Option<Instant> {
+    if self.pending.is_empty() {
+      return None;
+    }
+    let interval = Duration::from_secs(config.group_notices.min_interval_secs);
+    Some(match self.last_sent {
+      Some(last_sent) => last_sent + interval,
+      None => Instant::now(),
+    })
+  }
+
+  /// Everything pending as one message, if the rate limit lets it go now
+  pub fn take(&mut self, config: &Config) -> Option<String> {
+    if self.next_deadline(config)? > Instant::now() {
+      return None;
+    }
+    self.last_sent = Some(Instant::now());
+    let mut notices = std::mem::take(&mut self.pending);
+    match std::mem::take(&mut self.overflow) {
+      0 => {}
+      1 => notices.push("+1 more change".to_string()),
+      overflow => notices.push(format!("+{} more changes", overflow)),
+    }
+    Some(notices.join("\n"))
+  }
+}
+
+fn group_context(content: &Content) -> Option<&GroupContextV2> {
+  match &content.body {
+    ContentBody::DataMessage(message) => message.group_v2.as_ref(),
+    ContentBody::SynchronizeMessage(SyncMessage {
+      sent: Some(Sent {
+        message: Some(message), ..
+      }),
+      ..
+    }) => message.group_v2.as_ref(),
+    _ => None,
+  }
+}
+
+/// A group v2 update for the bridged group, which comes without a body
+pub fn is_group_change(content: &Content, config: &Config) -> bool {
+  group_context(content).is_some_and(|context| {
+    context.group_change.is_some() && context.master_key.as_deref() == Some(config.group_key.as_slice())
+  })
+}
+
+/// What changed between two versions of the group. `editor` is whoever made the change, which
+/// is how leaving and being removed tell apart
+pub fn group_events(before: &Group, after: &Group, editor: Uuid) -> Vec<GroupEvent> {
+  let members = |group: &Group| -> Vec<Uuid> { group.members.iter().map(|member| Uuid::from(member.aci)).collect() };
+  let (old, new) = (members(before), members(after));
+
+  let mut events: Vec<GroupEvent> = new
+    .iter()
+    .filter(|uuid| !old.contains(uuid))
+    .map(|uuid| GroupEvent::Joined(*uuid))
+    .collect();
+  for uuid in old.iter().filter(|uuid| !new.contains(uuid)) {
+    events.push(if *uuid == editor {
+      GroupEvent::Left(*uuid)
+    } else {
+      GroupEvent::Removed(*uuid)
+    });
+  }
+  if before.title != after.title {
+    events.push(GroupEvent::Renamed(after.title.clone()));
+  }
+  events
+}
+
+/// One line for the mesh about a group event
+pub fn notice(model: &Model, config: &Config, event: &GroupEvent) -> String {
+  let name = |uuid: &Uuid| shape(&model.names.get(uuid), &config.shaping);
+  match event {
+    GroupEvent::Joined(uuid) => format!("{} joined the signal side", name(uuid)),
+    GroupEvent::Left(uuid) => format!("{} left the signal side", name(uuid)),
+    GroupEvent::Removed(uuid) => format!("{} was removed from the signal side", name(uuid)),
+    GroupEvent::Renamed(title) => format!("the signal group is now called {}", shape(title, &config.shaping)),
+  }
+}
+
+/// The notices out on every radios bridged channel
+pub fn notices_to_mesh(model: &Model, config: &Config, body: String) -> Option<Action> {
+  let mut sends = vec![];
+  for (radio, (state, radio_config)) in model.radios.iter().zip(&config.radios).enumerate() {
+    let Some(index) = state.channels.bridged(config, radio_config) else {
+      warn!(radio = %state.name, "bridged channel isnt on the radio, not sending group notice");
+      continue;
+    };
+    sends.push(Action::SendToMesh {
+      radio,
+      body: body.clone(),
+      channel: index.into(),
+      destination: PacketDestination::Broadcast,
+      signal_message: None,
+      outbox_id: None,
+    });
+  }
+
+  info!(radios = sends.len(), "sending group notices to mesh");
+  if sends.len() > 1 {
+    Some(Action::Batch(sends))
+  } else {
+    sends.pop()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::test_config;
+
+  #[test]
+  fn a_mass_join_is_summed_up() {
+    let config = test_config();
+    let mut notices = GroupNotices::default();
+    for n in 0..8 {
+      notices.push(format!("person {} joined the signal side", n));
+    }
+
+    let sent = notices.take(&config).expect("nothing was sent yet, so no rate limit");
+    let lines: Vec<&str> = sent.lines().collect();
+    assert_eq!(lines.len(), MAX_PENDING + 1);
+    assert_eq!(lines[0], "person 0 joined the signal side");
+    assert_eq!(lines[MAX_PENDING], "+3 more changes");
+
+    // the count starts over with the next batch
+    notices.last_sent = None;
+    notices.push("person 8 joined the signal side".to_string());
+    assert_eq!(
+      notices.take(&config).as_deref(),
+      Some("person 8 joined the signal side")
+    );
+  }
+}
@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use anyhow::bail;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{EnvFilter, Layer, fmt, prelude::*};
+
+use crate::config::LoggingConfig;
+
+fn parse_rotation(rotation: &str) -> anyhow::Result<Rotation> {
+  Ok(match rotation {
+    "minutely" => Rotation::MINUTELY,
+    "hourly" => Rotation::HOURLY,
+    "daily" => Rotation::DAILY,
+    "never" => Rotation::NEVER,
+    other => bail!("unknown log rotation: {}", other),
+  })
+}
+
+/// Sets up the global `tracing` subscriber. Hang on to the returned guard for as long as
+/// the program runs, dropping it is what flushes the log file.
+pub fn init(config: &LoggingConfig) -> anyhow::Result<Option<WorkerGuard>> {
+  let filter = match EnvFilter::try_from_default_env() {
+    Ok(filter) => filter,
+    Err(_) => EnvFilter::try_new(&config.level)?,
+  };
+
+  let stdout_layer = if config.json {
+    fmt::layer().json().boxed()
+  } else {
+    fmt::layer().boxed()
+  };
+
+  let mut guard = None;
+  let file_layer = match &config.file {
+    Some(file) => {
+      let path = Path::new(file);
+      let directory = path.parent().unwrap_or(Path::new("."));
+      let Some(prefix) = path.file_name() else {
+        bail!("log file should be a file: {}", file);
+      };
+
+      let appender = RollingFileAppender::new(parse_rotation(&config.rotation)?, directory, prefix);
+      let (writer, file_guard) = tracing_appender::non_blocking(appender);
+      guard = Some(file_guard);
+
+      Some(if config.json {
+        fmt::layer().json().with_writer(writer).boxed()
+      } else {
+        fmt::layer().with_ansi(false).with_writer(writer).boxed()
+      })
+    }
+    None => None,
+  };
+
+  tracing_subscriber::registry()
+    .with(filter)
+    .with(stdout_layer)
+    .with(file_layer)
+    .try_init()?;
+
+  Ok(guard)
+}
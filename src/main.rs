@@ -1,13 +1,38 @@
-mod logger;
+mod admin;
+mod capture;
+mod channels;
+mod config;
+mod crypto;
+mod direct;
+mod group_notices;
+mod logging;
 mod meshy;
+mod messenger;
+mod metrics;
+#[cfg(test)]
+mod mock_radio;
 mod mysignal;
+mod names;
+mod node_notices;
+mod pairing;
+mod radio;
+mod radio_info;
+mod shaping;
 mod signal;
+mod status;
+mod store_forward;
+mod template;
+mod unishox2;
 mod update;
 
-use std::io::prelude::*;
-use std::{collections::HashMap, fmt::Debug, fs::File, hash::Hash, sync::Arc, vec};
+use std::sync::atomic::Ordering;
+use std::{collections::HashMap, fmt::Debug, hash::Hash, sync::Arc, time::Duration, vec};
 
 use presage::proto::DataMessage;
+use presage::proto::{
+  BodyRange,
+  body_range::{AssociatedValue, Style},
+};
 use presage::{
   libsignal_service::{
     Profile,
@@ -27,29 +52,44 @@ use presage_store_sqlite::{OnNewIdentity, SqliteStore};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use tokio::signal::ctrl_c;
+use tokio::signal::unix::{SignalKind, signal};
 use tokio::sync::mpsc;
+use tokio::time::{Instant, sleep_until, timeout};
+use tracing::{debug, error, info, warn};
 use url::Url;
 // use ratatui_image::{StatefulImage, picker::Picker, protocol::StatefulProtocol};
 
 use qrcodegen::QrCode;
 use qrcodegen::QrCodeEcc;
 // use crate::signal::*;
+use crate::admin::{PendingChannels, channel_admin_messages, psk_report};
+use crate::config::{Config, config_exists, parse_config};
+use crate::group_notices::{GroupEvent, GroupNotices, group_events, is_group_change, notice, notices_to_mesh};
 use crate::meshy::*;
-use crate::signal::{Cmd, config_dir_path, link_device};
+use crate::messenger::Messenger;
+use crate::metrics::{METRICS, inc};
+use crate::names::{SenderNames, resolve_sender};
+use crate::node_notices::{NodeWatch, quiet_nodes};
+use crate::pairing::Pairings;
+use crate::radio::{RadioLink, RadioState, connect_radio, next_packet};
+use crate::radio_info::radio_report;
+use crate::shaping::fragment;
+use crate::signal::link_device;
 use crate::signal::{default_db_path, list_groups};
+use crate::status::{radio_label, status_report};
+use crate::store_forward::{Outbox, load_pending_acks, save_pending_acks};
 use crate::update::*;
-use crate::{logger::Logger, mysignal::SignalSpawner, update::LinkingAction};
+use crate::{mysignal::SignalSpawner, update::LinkingAction};
 
 /// This example connects to a radio via serial and prints out all received packets.
 /// This example requires a powered and flashed Meshtastic radio.
 /// https://meshtastic.org/docs/supported-hardware
 // use std::io::{self, BufRead};
 mod dumb_packet_router;
-use dumb_packet_router::DumbPacketRouter;
 
-use meshtastic::api::StreamApi;
 use meshtastic::packet::{PacketDestination, PacketRouter};
-use meshtastic::protobufs::{Channel, ChannelSettings, FromRadio, MeshPacket, NodeInfo, User, mesh_packet};
+use meshtastic::protobufs::{Channel, FromRadio, MeshPacket, NodeInfo, User, mesh_packet};
 use meshtastic::types::{MeshChannel, NodeId};
 use meshtastic::utils;
 
@@ -70,28 +110,55 @@ pub struct Model {
   running_state: RunningState,
   contacts: Contacts,
   groups: Groups,
-  channels: Vec<ChannelSettings>,
+  /// one for every entry in `config.radios`, in the same order
+  radios: Vec<RadioState>,
+  /// a `/channels` change waiting on its confirm
+  pending_channels: Option<PendingChannels>,
   mesh_to_signal: HashMap<u32, SignalMessage>,
+  started_at: std::time::Instant,
+  signal_synced: bool,
+  /// which mesh nodes belong to which signal accounts
+  pairings: Pairings,
+  /// who last `/dm`ed each node and when, so its replies find their way back for a while
+  dm_threads: HashMap<u32, (Uuid, std::time::Instant)>,
+  /// joins and leaves waiting to go out to the mesh
+  group_notices: GroupNotices,
+  /// every node weve heard, for announcing them coming and going
+  node_watch: NodeWatch,
+  /// who we last heard on the mesh, on which radio and when
+  last_packet: Option<(usize, u32, std::time::Instant)>,
+  names: SenderNames,
+  /// when we last asked an unknown node for its info, so we dont keep pestering it
+  nodeinfo_requests: HashMap<u32, std::time::Instant>,
   // groups: Vec<Group,
   // chat_index: usize,
   account: Account,
 }
 
 impl Model {
-  fn init(manager: &mut MyManager) -> Self {
+  fn init(uuid: Uuid) -> Self {
     Model {
       account: Account {
         // name: "nan".to_string(),
         // username: "nan".to_string(),
         // number: PhoneNumber("idc".to_string()),
-        uuid: manager.registration_data().service_ids.aci,
+        uuid,
       },
       groups: Default::default(),
       contacts: Default::default(),
       running_state: Default::default(),
       mesh_to_signal: HashMap::new(),
-      // 8 configurable channels
-      channels: Vec::with_capacity(8),
+      radios: vec![],
+      started_at: std::time::Instant::now(),
+      signal_synced: false,
+      last_packet: None,
+      names: SenderNames::new(Duration::from_secs(60 * 60)),
+      nodeinfo_requests: HashMap::new(),
+      pending_channels: None,
+      pairings: Pairings::default(),
+      dm_threads: HashMap::new(),
+      group_notices: GroupNotices::default(),
+      node_watch: NodeWatch::default(),
     }
   }
 }
@@ -122,11 +189,17 @@ pub struct Reaction {
   author: Uuid,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SignalMessage {
   body: String,
   sender: Uuid,
   timestamp: u64,
+  /// the 1:1 chat it came from, if it wasnt the group
+  #[serde(default)]
+  contact: Option<Uuid>,
+  /// a `/dm` to one node, which hears about it when it doesnt make it too
+  #[serde(default)]
+  direct: bool,
 }
 
 #[derive(Hash, PartialEq, Eq, Debug)]
@@ -181,59 +254,6 @@ struct Account {
   uuid: Uuid,
 }
 
-#[derive(Deserialize, Serialize)]
-struct RawConfig {
-  group_key: String,
-  channel_index: usize,
-}
-
-#[derive(Deserialize, Serialize)]
-struct Config {
-  group_key: GroupMasterKeyBytes,
-  channel_index: usize,
-}
-
-impl From<RawConfig> for Config {
-  fn from(value: RawConfig) -> Self {
-    let almost_key = hex::decode(value.group_key).expect("failed to parse key\nshould parese to a [u8; 32]");
-    if almost_key.len() != 32 {
-      panic!("incorrect key length: {}", almost_key.len());
-    }
-    let mut key: [u8; 32] = [0; 32];
-    for (index, byte) in almost_key.iter().enumerate() {
-      key[index] = *byte;
-    }
-    // let key: GroupMasterKeyBytes = key;
-
-    Config {
-      group_key: key,
-      channel_index: value.channel_index,
-    }
-  }
-}
-
-fn config_path() -> String {
-  let mut dir = config_dir_path();
-  dir.push_str("config.toml");
-  dir
-}
-
-fn parse_config() -> Config {
-  let mut file = match File::open(config_path()) {
-    Ok(f) => f,
-    Err(err) => {
-      eprintln!("unable to open file 'config.toml'");
-      eprintln!("heres an error also {}", err);
-      panic!();
-    }
-  };
-  let mut contents = String::new();
-  file.read_to_string(&mut contents).expect("cmon no way this fails");
-
-  let raw: RawConfig = toml::from_str(&contents).expect("failed to parse config file");
-  raw.into()
-}
-
 fn draw_linking_screen(url: &Option<Url>) {
   let _block = "██";
 
@@ -282,7 +302,17 @@ fn draw_linking_screen(url: &Option<Url>) {
 #[allow(unexpected_cfgs)]
 #[tokio::main(flavor = "local")]
 async fn main() -> anyhow::Result<()> {
+  // the real config gets loaded once were linked and the groups are listed, since the group key
+  // comes from that listing. logging just needs to know early when there is one
+  let early_config = config_exists().then(parse_config);
+  let logging = early_config
+    .as_ref()
+    .map(|config| config.logging.clone())
+    .unwrap_or_default();
+  let _log_guard = logging::init(&logging)?;
+
   let (action_tx, mut action_rx) = mpsc::unbounded_channel();
+
   let db_path = default_db_path();
   let mut config_store = SqliteStore::open_with_passphrase(&db_path, "secret".into(), OnNewIdentity::Trust).await?;
 
@@ -328,7 +358,7 @@ async fn main() -> anyhow::Result<()> {
         Some(_) => {}
 
         None => {
-          Logger::log("I dont think this should ever happenn".to_string());
+          error!("action channel closed while linking");
         }
       }
     }
@@ -337,72 +367,86 @@ async fn main() -> anyhow::Result<()> {
     config_store = SqliteStore::open_with_passphrase(&db_path, "secret".into(), OnNewIdentity::Trust).await?;
   }
 
-  Logger::log("Linked!!!");
+  info!("linked");
 
-  let mut manager = Manager::load_registered(config_store)
+  let manager = Manager::load_registered(config_store)
     .await
     .expect("failed to make the manager");
 
   let groups = list_groups(&manager).await;
-  println!("heres the groups for ur convenience: ");
+  info!("heres the groups for ur convenience");
   for group in groups {
-    println!("key: {}, title; {}", hex::encode(group.0), group.1.title);
+    info!(key = %hex::encode(group.0), title = %group.1.title, "group");
   }
 
   let config = parse_config();
-  let thread = Thread::Group(config.group_key);
-
-  let mut model = Model::init(&mut manager);
 
+  let model = Model::init(manager.registration_data().service_ids.aci);
   let spawner = SignalSpawner::new(manager, action_tx.clone());
-  // get our contacts
+
+  run_bridge(&config, model, spawner, action_tx, action_rx).await
+}
+
+/// Everything after the signal side is up, shuttling messages between it and the radio until
+/// we get told to stop. Generic over the signal side so the tests can run it without an account.
+async fn run_bridge(
+  config: &Config,
+  mut model: Model,
+  mut spawner: impl Messenger,
+  action_tx: mpsc::UnboundedSender<Action>,
+  mut action_rx: mpsc::UnboundedReceiver<Action>,
+) -> anyhow::Result<()> {
+  let thread = Thread::Group(config.group_key);
+
+  model.names = SenderNames::new(Duration::from_secs(config.signal.name_ttl_secs));
+  // get our contacts, and the groups for the members who arent contacts
   let _result = update_contacts(&mut model, &spawner).await;
+  if let Err(err) = model.update_groups(&spawner).await {
+    warn!(%err, "failed to load groups");
+  }
 
-  let stream_api = StreamApi::new();
+  if let Some(listen) = &config.metrics.listen {
+    metrics::serve(listen).await?;
+  }
 
-  let available_ports = utils::stream::available_serial_ports()?;
-  println!("Available ports: {:?}", available_ports);
+  match utils::stream::available_serial_ports() {
+    Ok(available_ports) => info!(?available_ports, "available serial ports"),
+    Err(err) => warn!(%err, "couldnt list serial ports"),
+  }
   // println!("Enter the name of a port to connect to:");
   //
-  let port = String::from("/dev/ttyACM0");
-
-  let serial_stream = utils::stream::build_serial_stream(port, None, None, None)?;
-  let (mut decoded_listener, stream_api) = stream_api.connect(serial_stream).await;
-
-  let config_id = utils::generate_rand_id();
-  let mut stream_api = stream_api.configure(config_id).await?;
-
-  // let mut nodes = HashMap::<u32, meshtastic::protobufs::NodeInfo>::new();
-  let mut nodes = Nodes::new();
-
-  // let channel_config = protobufs::Channel {
-  //   index: 1,
-  //   settings: Some(ChannelSettings {
-  //     psk: vec![],
-  //     name: "gateway".to_string(),
-  //     id: 67,
-  //     uplink_enabled: true,
-  //     downlink_enabled: true,
-  //     module_settings: None,
-  //     channel_num: 0,
-  //   }),
-  //   role: 2,
-  // };
-
-  let (packet_id_tx, mut packet_id_rx) = mpsc::unbounded_channel::<u32>();
-  let mut packet_router = DumbPacketRouter::new(NodeId::new(2454871382), action_tx.clone(), packet_id_tx);
-
-  // println!(
-  //   "{:?}",
-  //   stream_api
-  //     .update_channel_config::<String, MyError, DumbPacketRouter>(&mut packet_router, channel_config)
-  //     .await
-  // );
-
-  // This loop can be broken with ctrl+c or by disconnecting
-  // the attached serial port.
-  Logger::log("listening for mesh packets...");
-  loop {
+
+  let mut outbox = Outbox::load(&config.store_forward);
+  model.pairings = Pairings::load();
+  model.node_watch = NodeWatch::load();
+
+  // every radio starts out "disconnected" and lets the reconnect logic do the first connection
+  // too, so a missing radio at startup doesnt stop the signal side (or the other radios) coming up
+  model.radios = config.radios.iter().map(RadioState::new).collect();
+  let mut links: Vec<RadioLink> = config
+    .radios
+    .iter()
+    .map(|radio| RadioLink::new(radio, action_tx.clone()))
+    .collect();
+
+  // whatever was still waiting on an ack when we last shut down. we dont know which radio sent
+  // it, but packet ids are random so letting every router watch for it is harmless
+  model.mesh_to_signal = load_pending_acks();
+  for id in model.mesh_to_signal.keys() {
+    for link in &mut links {
+      link.router.expect_ack(*id);
+    }
+  }
+
+  let mut sigterm = signal(SignalKind::terminate())?;
+  let shutdown_timeout = Duration::from_secs(config.shutdown_timeout_secs);
+  // gets pushed out for real once we start shutting down
+  let mut shutdown_deadline = Instant::now();
+
+  // This loop can be broken with ctrl+c, the radios coming and going
+  // is handled in here too.
+  info!(radios = links.len(), "listening for mesh packets...");
+  'bridge: loop {
     // let soon_to_be_legacy = decoded_listener.recv().await;
 
     // let mut current_action = if let Some(decdoed) = soon_to_be_legacy {
@@ -411,14 +455,54 @@ async fn main() -> anyhow::Result<()> {
     //   break;
     // };
 
+    let running = model.running_state == RunningState::Running;
+    let next_deadline = links.iter().filter_map(|link| link.next_deadline(running)).min();
+    let notice_deadline = model.group_notices.next_deadline(config);
+    let quiet_deadline = model.node_watch.next_deadline(config);
+    let save_deadline = model.node_watch.save_deadline();
+
     let mut current_action = tokio::select! {
-      decoded = decoded_listener.recv() => {
+      (radio, decoded) = next_packet(&mut links) => {
         if let Some(decdoed) = decoded {
-          Some(Action::FromRadio(decdoed))
+          links[radio].last_heard = Instant::now();
+          METRICS.heard_packet();
+          Some(Action::FromRadio { radio, packet: decdoed })
         } else {
-        break;
+          warn!(radio = %config.radios[radio].name, "radio connection closed");
+          Some(Action::RadioLost { radio })
       }}
 
+      // heartbeats, channel refreshes and reconnects, for whichever radio is up next
+      _ = sleep_until(next_deadline.unwrap_or_else(Instant::now)), if next_deadline.is_some() => {
+        links
+          .iter_mut()
+          .enumerate()
+          .find_map(|(radio, link)| link.due(radio, running))
+      }
+
+      _ = sleep_until(notice_deadline.unwrap_or_else(Instant::now)), if notice_deadline.is_some() => {
+        Some(Action::FlushGroupNotices)
+      }
+
+      _ = sleep_until(quiet_deadline.unwrap_or_else(Instant::now)), if quiet_deadline.is_some() => {
+        Some(Action::CheckQuietNodes)
+      }
+
+      _ = sleep_until(save_deadline.unwrap_or_else(Instant::now)), if save_deadline.is_some() => {
+        model.node_watch.save();
+        None
+      }
+
+      _ = ctrl_c() => Some(Action::Quit),
+      _ = sigterm.recv() => Some(Action::Quit),
+
+      _ = sleep_until(shutdown_deadline), if model.running_state == RunningState::OhShit => {
+        warn!(pending = model.mesh_to_signal.len(), "gave up waiting on acks");
+        break 'bridge;
+      }
+
+      Some(received) = spawner.next_message() => Some(Action::Receive(received)),
+
       action = action_rx.recv() => {
         action
       }
@@ -426,91 +510,513 @@ async fn main() -> anyhow::Result<()> {
 
     while let Some(action) = current_action {
       current_action = match action {
-        Action::FromRadio(decoded) => {
+        Action::FromRadio { radio, packet: decoded } => {
           // i love this packet router thing oh so much
-          packet_router.handle_packet_from_radio(decoded.clone());
-          handle_from_radio_packet(&mut model, &config, &mut nodes, decoded)
+          links[radio].router.handle_packet_from_radio(decoded.clone());
+          if let Some(node) = unknown_sender(&mut model, config, radio, &decoded) {
+            _ = action_tx.send(Action::RequestNodeInfo { radio, node });
+          }
+          let was_ready = model.radios[radio].ready();
+          let next = handle_from_radio_packet(&mut model, config, radio, decoded);
+          if let (false, Some(node)) = (was_ready, model.radios[radio].my_node_num) {
+            _ = action_tx.send(Action::RadioReady { radio, node });
+          }
+          next
+        }
+
+        Action::RequestNodeInfo { radio, node } => {
+          let state = &model.radios[radio];
+          if state.ready() {
+            // the firmware answers a nodeinfo with its own, as long as we send ours along
+            let me = state
+              .my_node_num
+              .and_then(|me| state.nodes.get(&me))
+              .and_then(|info| info.user.clone())
+              .unwrap_or_default();
+            debug!(radio = %state.name, node, "asking unknown node for its info");
+            // not waiting on an ack for this one
+            let result = links[radio]
+              .send(
+                me.encode_to_vec(),
+                protobufs::PortNum::NodeinfoApp,
+                PacketDestination::Node(node.into()),
+                0.into(),
+                false,
+                true,
+              )
+              .await;
+            if let Err(err) = result {
+              warn!(radio = %state.name, node, %err, "failed to request node info");
+            }
+          }
+          None
+        }
+
+        Action::RefreshChannels { radio } => {
+          if model.radios[radio].ready() {
+            debug!(radio = %config.radios[radio].name, "asking the radio for its channels");
+            for request in channel_requests() {
+              let result = links[radio]
+                .send(
+                  request.encode_to_vec(),
+                  protobufs::PortNum::AdminApp,
+                  PacketDestination::Local,
+                  0.into(),
+                  false,
+                  true,
+                )
+                .await;
+              if let Err(err) = result {
+                warn!(radio = %config.radios[radio].name, %err, "failed to ask the radio for its channels");
+                break;
+              }
+            }
+          }
+          None
+        }
+
+        Action::ApplyChannels {
+          radio,
+          channels,
+          description,
+          share_psk_with,
+        } => {
+          let reply = if model.radios[radio].ready() {
+            info!(radio = %config.radios[radio].name, change = %description, "changing radio channels");
+            let mut result = Ok(None);
+            for message in channel_admin_messages(&channels) {
+              result = links[radio]
+                .send(
+                  message.encode_to_vec(),
+                  protobufs::PortNum::AdminApp,
+                  PacketDestination::Local,
+                  0.into(),
+                  false,
+                  false,
+                )
+                .await;
+              if result.is_err() {
+                break;
+              }
+            }
+
+            match result {
+              Ok(_) => {
+                // a key we made up is no use until someone can put it on their node
+                if let Some(uuid) = share_psk_with {
+                  info!(%uuid, "sending the new psk to whoever confirmed it");
+                  _ = action_tx.send(Action::SendToContact {
+                    uuid,
+                    message: psk_report(&model.radios[radio].channels, &channels),
+                  });
+                }
+                for channel in channels {
+                  model.radios[radio].channels.update(channel);
+                }
+                // see what the radio actually ended up with
+                _ = action_tx.send(Action::RefreshChannels { radio });
+                format!("✅ done: {}", description)
+              }
+              Err(err) => {
+                error!(%err, "failed to change radio channels");
+                format!("❌ couldnt {}: {}", description, err)
+              }
+            }
+          } else {
+            format!("❌ {} is offline, couldnt {}", radio_label(config, radio), description)
+          };
+
+          Some(Action::SendToGroup {
+            message: reply,
+            ranges: vec![],
+            master_key: config.group_key,
+          })
         }
 
         Action::SendToMesh {
+          radio,
           body,
           channel,
           destination,
-          signal_message,
+          mut signal_message,
+          outbox_id,
         } => {
-          println!("\tsending to mesh...");
-          println!(
-            "{:?}",
-            stream_api
-              .send_mesh_packet(
-                &mut packet_router,
-                body.into_bytes().into(),
-                protobufs::PortNum::TextMessageApp,
-                destination,
-                channel,
-                true,
-                false,
-                true,
-                None,
-                None,
-              )
-              .await
-          );
+          let name = &config.radios[radio].name;
+          // once were shutting down new messages go in the outbox for next time instead
+          if model.radios[radio].ready() && model.running_state == RunningState::Running {
+            let max_bytes = config.shaping.max_bytes;
+            // compressing only pays off when it keeps a signal message in one packet, and only
+            // for whoever the config says can read it
+            let readers = match destination {
+              PacketDestination::Node(node) => Some(node.id()),
+              _ => None,
+            };
+            let compress = config
+              .mesh
+              .compresses_for(model.radios[radio].channels.name(channel.channel()), readers);
+            let compressed = if compress && signal_message.is_some() && body.len() > max_bytes {
+              Some(unishox2::compress(&body)).filter(|compressed| compressed.len() <= max_bytes)
+            } else {
+              None
+            };
+            let (mut fragments, portnum) = match &compressed {
+              Some(_) => (vec![body.clone()], protobufs::PortNum::TextMessageCompressedApp),
+              None => (fragment(&body, max_bytes), protobufs::PortNum::TextMessageApp),
+            };
+            info!(
+              radio = %name,
+              ?destination,
+              channel = channel.channel(),
+              bytes = body.len(),
+              fragments = fragments.len(),
+              compressed = compressed.as_ref().map(|compressed| compressed.len()),
+              "sending to mesh"
+            );
+
+            let mut last_id = None;
+            let mut failed_at = None;
+            for (index, fragment) in fragments.iter().enumerate() {
+              let payload = match &compressed {
+                Some(compressed) => compressed.clone(),
+                None => fragment.clone().into_bytes(),
+              };
+              match links[radio]
+                .send(payload, portnum, destination, channel, true, false)
+                .await
+              {
+                Ok(id) => last_id = id,
+                Err(err) => {
+                  error!(radio = %name, %err, fragment = index + 1, "failed to send to mesh");
+                  failed_at = Some(index);
+                  break;
+                }
+              }
+            }
 
-          if let Some(message) = signal_message {
-            if let Some(id) = packet_id_rx.recv().await {
-              println!("\tthis is our id: {}", id);
-              model.mesh_to_signal.insert(id, message);
+            if let (Some(0), Some(id)) = (failed_at, outbox_id) {
+              // already queued once and nothing went out, it goes back whole with its original timestamp
+              outbox.requeue(id);
+            } else if let (Some(0), None) = (failed_at, outbox_id) {
+              outbox.push(name, body, channel.channel(), destination, signal_message, false);
+            } else if let (Some(index), Some(id)) = (failed_at, outbox_id) {
+              // the fragments that made it stay sent, only the rest goes back in the queue
+              outbox.requeue_rest(id, fragments.split_off(index));
+            } else if let Some(index) = failed_at {
+              // the serial link is probably on its way out, dont lose the rest of the message over it
+              let remaining = fragments.len() - index;
+              for (offset, fragment) in fragments.into_iter().skip(index).enumerate() {
+                let message = if offset + 1 == remaining {
+                  signal_message.take()
+                } else {
+                  None
+                };
+                outbox.push(name, fragment, channel.channel(), destination, message, true);
+              }
+            } else {
+              if let Some(id) = outbox_id {
+                outbox.sent(id);
+              }
+              if let (Some(message), Some(id)) = (signal_message, last_id) {
+                // the reaction goes on once the last fragment makes it
+                debug!(
+                  packet_id = id,
+                  timestamp = message.timestamp,
+                  "waiting on ack for signal message"
+                );
+                model.mesh_to_signal.insert(id, message);
+              }
             }
+          } else if let Some(id) = outbox_id {
+            outbox.requeue(id);
+          } else {
+            // keep the signal side alive, anything for the mesh waits in the outbox until its back
+            outbox.push(name, body, channel.channel(), destination, signal_message, false);
           }
           None
         }
+        Action::ConnectRadio { radio } => match connect_radio(&config.radios[radio]).await {
+          Ok((listener, api)) => {
+            info!(radio = %config.radios[radio].name, port = %config.radios[radio].port, "radio connected");
+            links[radio].connected(listener, api);
+            let state = &mut model.radios[radio];
+            state.connected = true;
+            // configure makes the radio send all of this again, dont want duplicates. could
+            // also be a different radio than last time, nothing goes out until it tells us
+            state.reset();
+            METRICS
+              .radio_connected
+              .store(model.radios.iter().all(|state| state.connected), Ordering::Relaxed);
+            None
+          }
+          Err(err) => {
+            let link = &mut links[radio];
+            let delay = link.backoff.next();
+            warn!(radio = %config.radios[radio].name, %err, ?delay, "failed to connect to radio, trying again later");
+            link.next_reconnect = Instant::now() + delay;
+            None
+          }
+        },
+
+        Action::RadioReady { radio, node } => {
+          let name = &config.radios[radio].name;
+          info!(radio = %name, node, "radio is ready, sending from its node id");
+          links[radio].router.set_id(NodeId::new(node));
+
+          let queued = outbox.flush(radio, name);
+          let notice = format!(
+            "📡 {} is back online ({} queued messages going out)",
+            radio_label(config, radio),
+            queued.len()
+          );
+          for action in queued {
+            _ = action_tx.send(action);
+          }
+
+          if links[radio].announced_offline {
+            links[radio].announced_offline = false;
+            inc(&METRICS.radio_reconnects);
+            Some(Action::SendToGroup {
+              message: notice,
+              ranges: vec![],
+              master_key: config.group_key,
+            })
+          } else {
+            None
+          }
+        }
+
+        Action::RadioLost { radio } if links[radio].replaying() => {
+          // theres no radio to come back, and nobody on signal needs to hear about it
+          info!(radio = %config.radios[radio].name, "capture replay is over, stopping");
+          model.radios[radio].connected = false;
+          links[radio].listener = None;
+          links[radio].api = None;
+          Some(Action::Quit)
+        }
+        Action::RadioLost { radio } => {
+          model.radios[radio].connected = false;
+          METRICS.radio_connected.store(false, Ordering::Relaxed);
+          if let Some(stream_api) = links[radio].lost() {
+            // probably already gone but its worth a shot
+            _ = stream_api.disconnect().await;
+          }
+
+          if links[radio].announced_offline {
+            None
+          } else {
+            links[radio].announced_offline = true;
+            Some(Action::SendToGroup {
+              message: format!(
+                "📴 {} went offline, messages for the mesh will be held until it's back",
+                radio_label(config, radio)
+              ),
+              ranges: vec![],
+              master_key: config.group_key,
+            })
+          }
+        }
+
         Action::SendToGroup {
           message,
           ranges,
           master_key,
         } => {
-          println!("\tsending to signal...");
-          spawner.spawn(Cmd::SendToGroup {
-            message,
-            ranges,
-            master_key,
-            timestamp: Utc::now().timestamp_millis() as u64,
-            attachment_filepath: vec![],
-          });
+          info!(bytes = message.len(), "sending to signal");
+          spawner.send_to_group(message, ranges, master_key);
+          None
+        }
+        Action::SendToContact { uuid, message } => {
+          info!(%uuid, bytes = message.len(), "sending to signal contact");
+          spawner.send_to_contact(uuid, message);
           None
         }
         Action::Receive(received) => match received {
-          Received::Content(content) => handle_message(&mut model, &config, *content),
+          Received::Content(content) if is_group_change(&content, config) => {
+            // presage has already fetched the new version of the group by the time this gets here
+            let editor = content.metadata.sender.raw_uuid();
+            let before = model.groups.get(&config.group_key).cloned();
+            if let Err(err) = model.update_groups(&spawner).await {
+              warn!(%err, "failed to reload groups after a group change");
+            }
+            let events = match (&before, model.groups.get(&config.group_key)) {
+              (Some(before), Some(after)) => group_events(before, after, editor),
+              _ => vec![],
+            };
+            info!(%editor, events = events.len(), "bridged group changed");
+
+            if config.group_notices.enabled && !events.is_empty() {
+              for event in &events {
+                if let GroupEvent::Joined(uuid) = event {
+                  resolve_sender(&mut model, config, &spawner, *uuid).await;
+                }
+              }
+              for event in &events {
+                let notice = notice(&model, config, event);
+                model.group_notices.push(notice);
+              }
+              Some(Action::FlushGroupNotices)
+            } else {
+              None
+            }
+          }
+          // names only get looked up for what were actually going to pass on or answer
+          Received::Content(content) if wants_message(&model, config, &content) => {
+            resolve_sender(&mut model, config, &spawner, content.metadata.sender.raw_uuid()).await;
+            handle_message(&mut model, config, *content)
+          }
+          Received::Content(_) => None,
           Received::Contacts => {
             _ = update_contacts(&mut model, &spawner).await;
             None
             // update our in memory cache of contacts
             // _ = update_contacts(model, spawner).await;
           }
-          Received::QueueEmpty => None,
+          Received::QueueEmpty => {
+            model.signal_synced = true;
+            None
+          }
         },
 
         Action::MeshAck { packet, deliverd } => {
-          println!("got ack!!!");
-          if deliverd {
-            if let Some(message) = model.mesh_to_signal.remove(&packet.id) {
-              spawner.spawn(Cmd::ReactToThread {
-                thread: thread.clone(),
-                reaction: "✔️".to_string(),
-                timestamp: Utc::now().timestamp_millis() as u64,
-                target_timestamp: message.timestamp,
-                author_uuid: Some(message.sender),
-              });
+          info!(packet_id = packet.id, deliverd, "got ack");
+          if let Some(message) = model.mesh_to_signal.remove(&packet.id) {
+            let reply_thread = match message.contact {
+              Some(uuid) => Thread::Contact(uuid),
+              None => thread.clone(),
+            };
+            if deliverd {
+              spawner.react(reply_thread, "✔️", message.timestamp, message.sender);
+            } else if message.direct {
+              // nobody else is listening for a dm, so say it didnt make it
+              spawner.react(reply_thread, "❌", message.timestamp, message.sender);
+            }
+          }
+
+          None
+        }
+
+        Action::FlushGroupNotices => match model.group_notices.take(config) {
+          Some(notices) => notices_to_mesh(&model, config, notices),
+          None => None,
+        },
+
+        Action::CheckQuietNodes => quiet_nodes(&mut model, config),
+
+        Action::Status { reply_to } => {
+          let report = status_report(&model, config, outbox.len());
+          info!(?reply_to, "sending status");
+          match reply_to {
+            ReplyTo::Group => Some(Action::SendToGroup {
+              message: report,
+              ranges: vec![BodyRange {
+                start: Some(0),
+                length: Some("Gateway status".len() as u32),
+                associated_value: Some(AssociatedValue::Style(Style::Bold.into())),
+              }],
+              master_key: config.group_key,
+            }),
+            ReplyTo::Node { radio, node } => Some(Action::SendToMesh {
+              radio,
+              body: report,
+              channel: 0.into(),
+              destination: PacketDestination::Node(node.into()),
+              signal_message: None,
+              outbox_id: None,
+            }),
+          }
+        }
+
+        Action::RadioInfo { reply_to } => {
+          let title = |radio: usize| {
+            if config.radios.len() > 1 {
+              format!("Radio {}", config.radios[radio].name)
+            } else {
+              "Radio".to_string()
+            }
+          };
+          info!(?reply_to, "sending radio info");
+          match reply_to {
+            ReplyTo::Group => {
+              // every radio gets its own report, titles in bold
+              let mut message = String::new();
+              let mut ranges = vec![];
+              for (radio, state) in model.radios.iter().enumerate() {
+                if !message.is_empty() {
+                  message.push_str("\n\n");
+                }
+                let title = title(radio);
+                ranges.push(BodyRange {
+                  start: Some(message.encode_utf16().count() as u32),
+                  length: Some(title.encode_utf16().count() as u32),
+                  associated_value: Some(AssociatedValue::Style(Style::Bold.into())),
+                });
+                message.push_str(&radio_report(&title, &state.info, &state.nodes));
+              }
+              Some(Action::SendToGroup {
+                message,
+                ranges,
+                master_key: config.group_key,
+              })
             }
+            // just the radio they asked through, the rest is too much for the mesh
+            ReplyTo::Node { radio, node } => Some(Action::SendToMesh {
+              radio,
+              body: radio_report(&title(radio), &model.radios[radio].info, &model.radios[radio].nodes),
+              channel: 0.into(),
+              destination: PacketDestination::Node(node.into()),
+              signal_message: None,
+              outbox_id: None,
+            }),
           }
+        }
 
+        Action::Batch(actions) => {
+          for action in actions {
+            _ = action_tx.send(action);
+          }
+          None
+        }
+
+        Action::Quit => {
+          if model.running_state == RunningState::OhShit {
+            warn!("ok ok, leaving right now");
+            break 'bridge;
+          }
+
+          info!(
+            timeout = ?shutdown_timeout,
+            pending = model.mesh_to_signal.len(),
+            "shutting down, waiting on acks"
+          );
+          model.running_state = RunningState::OhShit;
+          shutdown_deadline = Instant::now() + shutdown_timeout;
           None
         }
         _ => None,
       }
     }
+
+    if model.running_state == RunningState::OhShit && model.mesh_to_signal.is_empty() {
+      break 'bridge;
+    }
+  }
+
+  save_pending_acks(&model.mesh_to_signal);
+  model.node_watch.save();
+
+  for link in &mut links {
+    if let Some(stream_api) = link.api.take() {
+      if let Err(err) = stream_api.disconnect().await {
+        warn!(radio = %link.config.name, %err, "radio didnt disconnect cleanly");
+      }
+    }
+  }
+
+  // let the signal side finish sending whatever we handed it
+  if timeout(shutdown_timeout, spawner.shutdown()).await.is_err() {
+    warn!("signal sends didnt finish in time, some may be lost");
   }
 
+  info!("bye");
   Ok(())
 }
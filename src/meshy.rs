@@ -1,11 +1,91 @@
-use meshtastic::protobufs::PortNum;
-use presage::proto::{
-  BodyRange,
-  body_range::{AssociatedValue, Style},
-};
+use meshtastic::protobufs::{PortNum, admin_message};
+use tracing::{debug, info, trace, warn};
 
+use crate::channels::CHANNEL_SLOTS;
+use crate::config::{NameDisplay, RadioConfig};
+use crate::crypto;
+use crate::direct::DM_THREAD_TIMEOUT;
+use crate::metrics::{METRICS, inc};
+use crate::names::find_people;
+use crate::node_notices::{mesh_announce, node_heard, node_known};
+use crate::pairing::{mesh_link, mesh_unlink};
+use crate::radio::RadioState;
+use crate::template::{TemplateVars, render};
+use crate::unishox2;
 use crate::*;
 
+pub const BROADCAST: u32 = 0xffffffff;
+
+/// "!a1b2c3d4" the way the apps show node ids
+pub fn parse_node_id(text: &str) -> Option<u32> {
+  u32::from_str_radix(text.trim().strip_prefix('!')?, 16).ok()
+}
+
+/// How we refer to a node in messages, falling back to whichever name it does have and
+/// finally the `!xxxxxxxx` id when we know nothing about it
+pub fn mesh_sender_name(nodes: &Nodes, node: u32, display: NameDisplay) -> String {
+  let id = format!("!{:08x}", node);
+  let Some(user) = nodes.get(&node).and_then(|info| info.user.as_ref()) else {
+    return id;
+  };
+
+  let long = user.long_name.trim();
+  let short = user.short_name.trim();
+  match display {
+    NameDisplay::Long if !long.is_empty() => long.to_string(),
+    NameDisplay::Short if !short.is_empty() => short.to_string(),
+    NameDisplay::Both if !long.is_empty() && !short.is_empty() => format!("{} ({})", long, short),
+    _ if !long.is_empty() => long.to_string(),
+    _ if !short.is_empty() => short.to_string(),
+    _ => id,
+  }
+}
+
+/// The node a mesh packet came from, if we have no name for it and havent asked it recently
+pub fn unknown_sender(model: &mut Model, config: &Config, radio: usize, packet: &FromRadio) -> Option<u32> {
+  let Some(meshtastic::protobufs::from_radio::PayloadVariant::Packet(mesh_packet)) = &packet.payload_variant else {
+    return None;
+  };
+  let from = mesh_packet.from;
+
+  // any of our own radios count as known
+  if model.radios.iter().any(|state| state.my_node_num == Some(from))
+    || model.radios[radio]
+      .nodes
+      .get(&from)
+      .is_some_and(|info| info.user.is_some())
+  {
+    return None;
+  }
+
+  let wait = Duration::from_secs(config.mesh.nodeinfo_request_secs);
+  let minute = Duration::from_secs(60);
+  // nothing older than either window matters anymore
+  model
+    .nodeinfo_requests
+    .retain(|_, asked| asked.elapsed() < wait.max(minute));
+  if model
+    .nodeinfo_requests
+    .get(&from)
+    .is_some_and(|asked| asked.elapsed() < wait)
+  {
+    return None;
+  }
+
+  let last_minute = model
+    .nodeinfo_requests
+    .values()
+    .filter(|asked| asked.elapsed() < minute)
+    .count();
+  if last_minute >= config.mesh.nodeinfo_requests_per_minute {
+    debug!(node = from, last_minute, "asked enough unknown nodes for now");
+    return None;
+  }
+
+  model.nodeinfo_requests.insert(from, std::time::Instant::now());
+  Some(from)
+}
+
 /// A helper function to handle packets coming directly from the radio connection.
 /// The Meshtastic `PhoneAPI` will return decoded `FromRadio` packets, which
 /// can then be handled based on their payload variant. Note that the payload
@@ -13,16 +93,20 @@ use crate::*;
 pub fn handle_from_radio_packet(
   model: &mut Model,
   config: &Config,
-  nodes: &mut Nodes,
+  radio: usize,
   from_radio_packet: meshtastic::protobufs::FromRadio,
 ) -> Option<Action> {
+  let state = &mut model.radios[radio];
   // let cloned_packet = from_radio_packet.clone();
   // println!("heres all the packets: {:?}", cloned_packet);
   // Remove `None` variants to get the payload variant
   let payload_variant = match from_radio_packet.payload_variant {
     Some(payload_variant) => payload_variant,
     None => {
-      println!("Received FromRadio packet with no payload variant, not handling...");
+      debug!(
+        id = from_radio_packet.id,
+        "FromRadio packet with no payload variant, not handling"
+      );
       return None;
     }
   };
@@ -32,19 +116,77 @@ pub fn handle_from_radio_packet(
   // can be matched on, and the appropriate user-defined action can be taken.
   match payload_variant {
     meshtastic::protobufs::from_radio::PayloadVariant::Channel(channel) => {
-      println!("Received channel packet: {:?}", channel);
-      // just gyatta be sure
-      // assert_eq!(model.channels.len(), channel.index as usize);
-
-      if let Some(settings) = channel.settings {
-        model.channels.push(settings);
+      info!(radio = %state.name, index = channel.index, role = channel.role, "received channel");
+      state.channels.update(channel);
+    }
+    meshtastic::protobufs::from_radio::PayloadVariant::MyInfo(my_info) => {
+      info!(radio = %state.name, node = my_info.my_node_num, "got our own node info");
+      state.my_node_num = Some(my_info.my_node_num);
+      state.info.my_info = Some(my_info);
+    }
+    meshtastic::protobufs::from_radio::PayloadVariant::Metadata(metadata) => {
+      info!(
+        radio = %state.name,
+        firmware = %metadata.firmware_version,
+        hardware = metadata.hw_model().as_str_name(),
+        "got radio metadata"
+      );
+      state.info.metadata = Some(metadata);
+    }
+    meshtastic::protobufs::from_radio::PayloadVariant::Config(radio_config) => {
+      debug!(?radio_config, "received radio config");
+      state.info.update_config(radio_config);
+      // unnamed channels are named after the preset, which only shows up in here
+      state.channels.set_preset_name(state.info.preset_name());
+    }
+    meshtastic::protobufs::from_radio::PayloadVariant::ModuleConfig(module_config) => {
+      debug!(?module_config, "received module config");
+      state.info.update_module_config(module_config);
+    }
+    meshtastic::protobufs::from_radio::PayloadVariant::ConfigCompleteId(config_id) => {
+      info!(
+        radio = %state.name,
+        config_id,
+        hardware = state.info.hardware(),
+        role = state.info.role(),
+        preset = ?state.info.preset_name(),
+        "radio finished configuring"
+      );
+      for warning in state.info.warnings() {
+        warn!(radio = %state.name, %warning, "radio config problem");
       }
     }
     meshtastic::protobufs::from_radio::PayloadVariant::NodeInfo(node_info) => {
-      nodes.insert(node_info.num, node_info);
+      debug!(
+        node = node_info.num,
+        name = node_info.user.as_ref().map(|user| user.long_name.as_str()),
+        last_heard = node_info.last_heard,
+        "received node info"
+      );
+      if node_info.last_heard != 0 {
+        METRICS.heard_node(node_info.num, node_info.last_heard as i64);
+      }
+      let (node, last_heard) = (node_info.num, node_info.last_heard);
+      state.nodes.insert(node, node_info);
+      node_known(model, config, node, last_heard);
     }
     meshtastic::protobufs::from_radio::PayloadVariant::Packet(mesh_packet) => {
-      return handle_mesh_packet(mesh_packet, nodes, config);
+      METRICS.heard_node(mesh_packet.from, Utc::now().timestamp());
+      model.last_packet = Some((radio, mesh_packet.from, std::time::Instant::now()));
+      // the radio answering our own channel requests, see `channel_requests`
+      if let Some(channel) = admin_channel_response(&mesh_packet) {
+        update_channel(state, config, &config.radios[radio], channel);
+        return None;
+      }
+      let from = mesh_packet.from;
+      // hop_start is 0 on old firmware that doesnt tell us
+      let hops = (mesh_packet.hop_start != 0).then(|| mesh_packet.hop_start.saturating_sub(mesh_packet.hop_limit));
+      let action = handle_mesh_packet(mesh_packet, model, radio, config);
+      // after the packet, so a node info packet has already told us its name
+      return match (action, node_heard(model, config, radio, from, hops)) {
+        (Some(action), Some(notice)) => Some(Action::Batch(vec![action, notice])),
+        (action, notice) => action.or(notice),
+      };
     }
     _ => {
       // println!("Received other FromRadio packet, not handling...");
@@ -54,28 +196,126 @@ pub fn handle_from_radio_packet(
   None
 }
 
+/// Slots in a channel that changed after configure, and makes some noise if that moved the
+/// bridge. During configure the table is still filling up, so it would just be noise then.
+fn update_channel(state: &mut RadioState, config: &Config, radio_config: &RadioConfig, channel: Channel) {
+  let index = channel.index;
+  let bridged = state.channels.bridged(config, radio_config);
+  if !state.channels.update(channel) {
+    return;
+  }
+  debug!(radio = %state.name, index, "channel changed");
+
+  let now_bridged = state.channels.bridged(config, radio_config);
+  if now_bridged != bridged {
+    match now_bridged {
+      Some(slot) => info!(radio = %state.name, ?bridged, slot, "bridged channel moved"),
+      None => warn!(radio = %state.name, ?bridged, "bridged channel is gone from the radio"),
+    }
+  }
+}
+
+fn admin_channel_response(mesh_packet: &MeshPacket) -> Option<Channel> {
+  let Some(protobufs::mesh_packet::PayloadVariant::Decoded(data)) = &mesh_packet.payload_variant else {
+    return None;
+  };
+  if data.portnum() != PortNum::AdminApp {
+    return None;
+  }
+
+  match protobufs::AdminMessage::decode(data.payload.as_slice())
+    .ok()?
+    .payload_variant?
+  {
+    admin_message::PayloadVariant::GetChannelResponse(channel) => Some(channel),
+    _ => None,
+  }
+}
+
+/// Admin requests for every channel slot. The radio only volunteers its channels while
+/// configuring, so this is how we find out about changes made from the app.
+pub fn channel_requests() -> Vec<protobufs::AdminMessage> {
+  (0..CHANNEL_SLOTS as u32)
+    .map(|slot| protobufs::AdminMessage {
+      // these are 1 based, for some reason
+      payload_variant: Some(admin_message::PayloadVariant::GetChannelRequest(slot + 1)),
+      ..Default::default()
+    })
+    .collect()
+}
+
+/// Text payloads, decompressing the unishox2 ones
+fn decode_text(data: &protobufs::Data) -> Option<String> {
+  if data.portnum() == PortNum::TextMessageCompressedApp {
+    match unishox2::decompress(&data.payload) {
+      Ok(text) => Some(text),
+      Err(err) => {
+        warn!(%err, "couldnt decompress text message");
+        None
+      }
+    }
+  } else {
+    Some(String::from_utf8_lossy(&data.payload).into_owned())
+  }
+}
+
 /// A helper function to handle `MeshPacket` messages, which are a subset
 /// of all `FromRadio` messages. Note that the payload variant can be `None`,
-/// and that the payload variant can be `Encrypted`, in which case the packet
-/// should be ignored within client applications.
+/// and that the payload variant can be `Encrypted`, in which case we try the
+/// keys of the channels we know and ignore the packet if none of them fit.
 ///
 /// Mesh packets are the most commonly used type of packet, and are usually
 /// what people are referring to when they talk about "packets."
-pub fn handle_mesh_packet(mesh_packet: protobufs::MeshPacket, nodes: &Nodes, config: &Config) -> Option<Action> {
-  let cloned_packet = mesh_packet.clone();
-  // println!("mesh packet for ya: {:?}", cloned_packet);
-  println!();
+pub fn handle_mesh_packet(
+  mut mesh_packet: protobufs::MeshPacket,
+  model: &mut Model,
+  radio: usize,
+  config: &Config,
+) -> Option<Action> {
+  trace!(?mesh_packet, "mesh packet");
+  let state = &model.radios[radio];
+  let radio_config = &config.radios[radio];
+  let bridged = state.channels.bridged(config, radio_config);
   // Remove `None` variants to get the payload variant
 
-  // Only handle decoded (unencrypted) mesh packets
-  let packet_data = match mesh_packet.payload_variant {
+  let packet_data = match mesh_packet.payload_variant.take() {
     Some(protobufs::mesh_packet::PayloadVariant::Decoded(decoded_mesh_packet)) => decoded_mesh_packet,
-    Some(protobufs::mesh_packet::PayloadVariant::Encrypted(_encrypted_mesh_packet)) => {
-      // println!("Received encrypted mesh packet, not handling...");
-      return None;
+    // encrypted packets have the channel hash where the index would be
+    Some(protobufs::mesh_packet::PayloadVariant::Encrypted(encrypted)) => {
+      match crypto::try_decrypt(
+        &state.channels,
+        mesh_packet.channel,
+        mesh_packet.id,
+        mesh_packet.from,
+        &encrypted,
+      ) {
+        Some((index, decrypted)) => {
+          debug!(
+            from = mesh_packet.from,
+            packet_id = mesh_packet.id,
+            channel = index,
+            "decrypted mesh packet"
+          );
+          mesh_packet.channel = index;
+          decrypted
+        }
+        None => {
+          trace!(
+            from = mesh_packet.from,
+            packet_id = mesh_packet.id,
+            hash = mesh_packet.channel,
+            "encrypted mesh packet we dont have the key for, not handling"
+          );
+          return None;
+        }
+      }
     }
     None => {
-      println!("Received mesh packet with no payload variant, not handling...");
+      debug!(
+        from = mesh_packet.from,
+        packet_id = mesh_packet.id,
+        "mesh packet with no payload variant, not handling"
+      );
       return None;
     }
   };
@@ -94,70 +334,358 @@ pub fn handle_mesh_packet(mesh_packet: protobufs::MeshPacket, nodes: &Nodes, con
       // println!("Received position packet: {:?}", decoded_position);
     }
 
-    meshtastic::protobufs::PortNum::TextMessageApp => match mesh_packet.channel {
-      0 => {
+    PortNum::TextMessageApp | PortNum::TextMessageCompressedApp => match mesh_packet.channel {
+      // dms come in on the primary. only ones addressed to this radio count, anything broadcast
+      // on a public primary is just someone talking on the mesh
+      0 if state.my_node_num == Some(mesh_packet.to) => {
         // println!("heres the whole packet: {:#?}", &cloned_packet);
-        let decoded_text_message = String::from_utf8(packet_data.payload).unwrap();
+        let decoded_text_message = decode_text(&packet_data)?;
 
-        println!("Received DM message: {:?}", &decoded_text_message);
+        info!(
+          from = mesh_packet.from,
+          packet_id = mesh_packet.id,
+          rx_time = mesh_packet.rx_time,
+          bytes = decoded_text_message.len(),
+          "received DM"
+        );
+        debug!(from = mesh_packet.from, text = %decoded_text_message, "DM text");
 
         if decoded_text_message == "/ping" {
           return Some(Action::SendToMesh {
+            radio,
             body: "pong!".to_string(),
             channel: 0.into(),
             destination: PacketDestination::Node(mesh_packet.from.into()),
             signal_message: None,
+            outbox_id: None,
+          });
+        }
+
+        if decoded_text_message == "/status" {
+          return Some(Action::Status {
+            reply_to: ReplyTo::Node {
+              radio,
+              node: mesh_packet.from,
+            },
+          });
+        }
+
+        if decoded_text_message == "/radio" {
+          return Some(Action::RadioInfo {
+            reply_to: ReplyTo::Node {
+              radio,
+              node: mesh_packet.from,
+            },
+          });
+        }
+
+        if let Some(code) = decoded_text_message.strip_prefix("/link") {
+          if code.is_empty() || code.starts_with(' ') {
+            return Some(mesh_link(model, radio, mesh_packet.from, code.trim()));
+          }
+        }
+
+        if decoded_text_message == "/unlink" {
+          return Some(mesh_unlink(model, radio, mesh_packet.from));
+        }
+
+        if let Some(args) = decoded_text_message.strip_prefix("/announce") {
+          if args.is_empty() || args.starts_with(' ') {
+            return Some(mesh_announce(model, radio, mesh_packet.from, args.trim()));
+          }
+        }
+
+        if let Some(addressed) = decoded_text_message.strip_prefix('@') {
+          let sender = MeshSender::new(model, &state.nodes, config, &mesh_packet);
+          let vars = sender.vars("", &state.name, "");
+          return Some(address_signal_user(
+            model,
+            config,
+            radio,
+            mesh_packet.from,
+            &vars,
+            addressed,
+          ));
+        }
+
+        // an answer to a `/dm` from signal
+        model
+          .dm_threads
+          .retain(|_, (_, sent_at)| sent_at.elapsed() < DM_THREAD_TIMEOUT);
+        if let Some(&(uuid, _)) = model.dm_threads.get(&mesh_packet.from) {
+          let sender = MeshSender::new(model, &state.nodes, config, &mesh_packet);
+          let vars = sender.vars("", &state.name, &decoded_text_message);
+          info!(from = mesh_packet.from, %uuid, "passing dm reply on to signal");
+          inc(&METRICS.mesh_to_signal);
+          return Some(Action::SendToContact {
+            uuid,
+            message: render(&config.templates.mesh_to_signal, &vars).text,
           });
         }
       }
-      1 => {
+      // a dm between two other nodes, even on the bridged channel its none of the groups business
+      channel if mesh_packet.to != BROADCAST => {
+        debug!(
+          from = mesh_packet.from,
+          to = mesh_packet.to,
+          channel,
+          "dm for someone else"
+        );
+      }
+      channel if Some(channel) == bridged => {
+        // one of our radios hearing what another one sent (or relayed), which already came from
+        // signal or got bridged on the way in. passing it on again would loop it forever
+        if model
+          .radios
+          .iter()
+          .any(|state| state.my_node_num == Some(mesh_packet.from))
+        {
+          debug!(
+            from = mesh_packet.from,
+            packet_id = mesh_packet.id,
+            "our own radio, not bridging"
+          );
+          return None;
+        }
+
         // println!("heres the whole packet: {:#?}", &cloned_packet);
-        let decoded_text_message = String::from_utf8(packet_data.payload).unwrap();
+        let decoded_text_message = decode_text(&packet_data)?;
 
-        println!("Received text message from channel: {:?}", &decoded_text_message);
+        info!(
+          from = mesh_packet.from,
+          packet_id = mesh_packet.id,
+          channel = mesh_packet.channel,
+          rx_time = mesh_packet.rx_time,
+          bytes = decoded_text_message.len(),
+          "received channel message"
+        );
+        debug!(from = mesh_packet.from, text = %decoded_text_message, "channel message text");
 
         if decoded_text_message == "/ping" {
           return Some(Action::SendToMesh {
+            radio,
             body: "pong!".to_string(),
-            channel: 1.into(),
+            channel: channel.into(),
             destination: PacketDestination::Broadcast,
             signal_message: None,
+            outbox_id: None,
           });
         }
 
-        let name = match &nodes[&mesh_packet.from].user {
-          Some(usr) => usr.long_name.clone(),
-          None => format!("{:x}", mesh_packet.from),
-        };
+        let sender = MeshSender::new(model, &state.nodes, config, &mesh_packet);
+        let channel = state.channels.name(channel).unwrap_or_default();
 
-        let message = format!("{}:\n{}", name, decoded_text_message);
+        // for one person on signal, not the whole group
+        if let Some(addressed) = decoded_text_message.strip_prefix('@') {
+          let vars = sender.vars(channel, &state.name, "");
+          return Some(address_signal_user(
+            model,
+            config,
+            radio,
+            mesh_packet.from,
+            &vars,
+            addressed,
+          ));
+        }
+
+        let vars = sender.vars(channel, &state.name, &decoded_text_message);
+        let rendered = render(&config.templates.mesh_to_signal, &vars);
+        info!(
+          radio = %state.name,
+          from = mesh_packet.from,
+          packet_id = mesh_packet.id,
+          "bridging mesh message to signal"
+        );
+        inc(&METRICS.mesh_to_signal);
 
-        return Some(Action::SendToGroup {
-          message,
+        let to_signal = Action::SendToGroup {
+          ranges: rendered.name_ranges(),
+          message: rendered.text,
           master_key: config.group_key,
-          ranges: vec![BodyRange {
-            start: Some(0),
-            length: Some(name.len() as u32),
-            associated_value: Some(AssociatedValue::Style(Style::Bold.into())),
-          }],
+        };
+        return Some(match relay(&model.radios, config, radio_config, &vars) {
+          Some(relayed) => Action::Batch(vec![to_signal, relayed]),
+          None => to_signal,
         });
       }
-      _ => println!("invalid portnum but also shouldnt see this"),
+      channel => debug!(from = mesh_packet.from, channel, "text on a channel we dont bridge"),
+    },
+
+    PortNum::NodeinfoApp => match protobufs::User::decode(packet_data.payload.as_slice()) {
+      Ok(user) => {
+        debug!(from = mesh_packet.from, name = %user.long_name, "node info over the mesh");
+        let node = model.radios[radio]
+          .nodes
+          .entry(mesh_packet.from)
+          .or_insert_with(|| NodeInfo {
+            num: mesh_packet.from,
+            ..Default::default()
+          });
+        node.user = Some(user);
+        if mesh_packet.rx_time != 0 {
+          node.last_heard = mesh_packet.rx_time;
+        }
+      }
+      Err(err) => debug!(from = mesh_packet.from, %err, "bad node info packet"),
     },
 
     PortNum::RoutingApp => {
-      println!("routing this routing that: {:?}", packet_data)
+      debug!(
+        from = mesh_packet.from,
+        request_id = packet_data.request_id,
+        "routing packet"
+      )
     }
 
     meshtastic::protobufs::PortNum::WaypointApp => {
-      let decoded_waypoint = meshtastic::protobufs::Waypoint::decode(packet_data.payload.as_slice()).unwrap();
-
-      println!("Received waypoint packet: {:?}", decoded_waypoint);
+      match meshtastic::protobufs::Waypoint::decode(packet_data.payload.as_slice()) {
+        Ok(decoded_waypoint) => debug!(from = mesh_packet.from, ?decoded_waypoint, "received waypoint"),
+        Err(err) => debug!(from = mesh_packet.from, %err, "bad waypoint packet"),
+      }
     }
     _ => {
-      println!("Received mesh packet on port {:?}, not handling...", packet_data.portnum);
+      trace!(
+        from = mesh_packet.from,
+        portnum = packet_data.portnum,
+        "not handling mesh packet"
+      );
     }
   }
 
   None
 }
+
+/// Who sent a mesh packet, everything about them a template might want
+struct MeshSender {
+  name: String,
+  short: String,
+  id: String,
+  hops: Option<u32>,
+  snr: Option<f32>,
+  time: String,
+}
+
+impl MeshSender {
+  fn new(model: &Model, nodes: &Nodes, config: &Config, mesh_packet: &MeshPacket) -> Self {
+    let from = mesh_packet.from;
+    let name = match model.pairings.uuid_for(from) {
+      // linked nodes go by their owners signal name, the check says we know its really them
+      Some(uuid) => format!("{} ✓", model.names.get(&uuid)),
+      None => mesh_sender_name(nodes, from, config.mesh.name_display),
+    };
+    let time = match DateTime::from_timestamp(mesh_packet.rx_time as i64, 0) {
+      Some(time) if mesh_packet.rx_time != 0 => time,
+      _ => Utc::now(),
+    }
+    .with_timezone(&chrono::Local)
+    .format("%H:%M")
+    .to_string();
+
+    Self {
+      name,
+      short: mesh_sender_name(nodes, from, NameDisplay::Short),
+      id: format!("!{:08x}", from),
+      // hop_start is 0 on old firmware that doesnt tell us
+      hops: (mesh_packet.hop_start != 0).then(|| mesh_packet.hop_start.saturating_sub(mesh_packet.hop_limit)),
+      snr: (mesh_packet.rx_snr != 0.0).then_some(mesh_packet.rx_snr),
+      time,
+    }
+  }
+
+  fn vars<'a>(&'a self, channel: &'a str, radio: &'a str, body: &'a str) -> TemplateVars<'a> {
+    TemplateVars {
+      name: &self.name,
+      short: &self.short,
+      id: &self.id,
+      channel,
+      radio,
+      hops: self.hops,
+      snr: self.snr,
+      time: &self.time,
+      body,
+    }
+  }
+}
+
+/// `@name message` from the mesh goes to that one person on signal. The sender gets a dm back
+/// saying who it went to, or who they might have meant.
+fn address_signal_user(
+  model: &Model,
+  config: &Config,
+  radio: usize,
+  from: u32,
+  vars: &TemplateVars,
+  addressed: &str,
+) -> Action {
+  let reply = |body: String| Action::SendToMesh {
+    radio,
+    body,
+    channel: 0.into(),
+    destination: PacketDestination::Node(from.into()),
+    signal_message: None,
+    outbox_id: None,
+  };
+
+  let (query, body) = addressed.split_once(char::is_whitespace).unwrap_or((addressed, ""));
+  let body = body.trim();
+  if query.is_empty() || body.is_empty() {
+    return reply("usage: @name message".to_string());
+  }
+
+  let mut people = find_people(model, query);
+  match people.len() {
+    0 => reply(format!("nobody on signal goes by {}", query)),
+    1 => {
+      let (uuid, name) = people.remove(0);
+      info!(from, %uuid, "sending mesh message to one signal user");
+      inc(&METRICS.mesh_to_signal);
+      let rendered = render(&config.templates.mesh_to_signal, &TemplateVars { body, ..*vars });
+      Action::Batch(vec![
+        Action::SendToContact {
+          uuid,
+          message: rendered.text,
+        },
+        reply(format!("✉️ sent to {}", name)),
+      ])
+    }
+    _ => {
+      // the mesh doesnt need a phone book
+      let options: Vec<String> = people
+        .iter()
+        .take(5)
+        .map(|(_, name)| format!("@{}", name.replace(' ', "_")))
+        .collect();
+      reply(format!(
+        "more than one {} on signal, send it again to one of {}",
+        query,
+        options.join(", ")
+      ))
+    }
+  }
+}
+
+/// The copy of a bridged message that goes to the radio this one relays to, if it does
+fn relay(radios: &[RadioState], config: &Config, radio_config: &RadioConfig, vars: &TemplateVars) -> Option<Action> {
+  let relay = radio_config.relay_to.as_ref()?;
+  // the config wont load if this isnt there
+  let target = config.radios.iter().position(|other| other.name == relay.radio)?;
+  let channels = &radios[target].channels;
+  let channel = match &relay.channel {
+    Some(name) => channels.find(name),
+    None => channels.bridged(config, &config.radios[target]),
+  };
+  let Some(channel) = channel else {
+    warn!(from = %radio_config.name, to = %relay.radio, "relay channel isnt on the other radio, not relaying");
+    return None;
+  };
+
+  debug!(from = %radio_config.name, to = %relay.radio, channel, "relaying mesh message");
+  Some(Action::SendToMesh {
+    radio: target,
+    body: render(&config.templates.relay, vars).text,
+    channel: channel.into(),
+    destination: PacketDestination::Broadcast,
+    signal_message: None,
+    outbox_id: None,
+  })
+}
@@ -0,0 +1,331 @@
+use chrono::Utc;
+use presage::libsignal_service::zkgroup::GroupMasterKeyBytes;
+use presage::model::contacts::Contact;
+use presage::model::groups::Group;
+use presage::model::messages::Received;
+use presage::proto::BodyRange;
+use presage::store::Thread;
+
+use crate::signal::Cmd;
+use crate::{Profile, ProfileKey, Uuid};
+
+/// Everything the bridge needs from the signal side, both ways
+pub trait Messenger {
+  /// Fire and forget, failures get logged on the other end
+  fn spawn(&self, cmd: Cmd);
+
+  /// The next thing signal has for us, `None` once it never will again
+  async fn next_message(&mut self) -> Option<Received>;
+
+  async fn list_contacts(&self) -> anyhow::Result<Vec<Contact>>;
+
+  async fn list_groups(&self) -> Vec<(GroupMasterKeyBytes, Group)>;
+
+  async fn retrieve_profile(&self, uuid: Uuid, profile_key: Option<ProfileKey>) -> anyhow::Result<Profile>;
+
+  /// Waits for whatever was already spawned to go out
+  async fn shutdown(self);
+
+  fn send_to_group(&self, message: String, ranges: Vec<BodyRange>, master_key: GroupMasterKeyBytes) {
+    self.spawn(Cmd::SendToGroup {
+      message,
+      ranges,
+      master_key,
+      timestamp: Utc::now().timestamp_millis() as u64,
+      attachment_filepath: vec![],
+    });
+  }
+
+  /// Plain text into a group or 1:1 chat, whichever the thread is
+  fn send_to_thread(&self, thread: Thread, message: String) {
+    self.spawn(Cmd::SendToThread {
+      thread,
+      message,
+      quote: None,
+      timestamp: Utc::now().timestamp_millis() as u64,
+      attachment_filepath: vec![],
+    });
+  }
+
+  /// A 1:1 message, for things that are only meant for one person
+  fn send_to_contact(&self, uuid: Uuid, message: String) {
+    self.send_to_thread(Thread::Contact(uuid), message);
+  }
+
+  fn react(&self, thread: Thread, reaction: &str, target_timestamp: u64, author: Uuid) {
+    self.spawn(Cmd::ReactToThread {
+      thread,
+      reaction: reaction.to_string(),
+      timestamp: Utc::now().timestamp_millis() as u64,
+      target_timestamp,
+      author_uuid: Some(author),
+    });
+  }
+}
+
+#[cfg(test)]
+pub mod tests {
+  use std::cell::RefCell;
+  use std::rc::Rc;
+  use std::time::Duration;
+
+  use meshtastic::protobufs::{MeshPacket, PortNum, mesh_packet, to_radio};
+  use presage::libsignal_service::content::{Content, ContentBody, Metadata};
+  use presage::libsignal_service::protocol::{DeviceId, ServiceId};
+  use presage::proto::{DataMessage, GroupContextV2};
+  use tokio::sync::mpsc;
+  use tokio::task::LocalSet;
+  use tokio::time::{sleep, timeout};
+  use tracing::debug;
+
+  use super::*;
+  use crate::config::{Config, test_config};
+  use crate::mock_radio::{MockRadioHandle, take_connected};
+  use crate::update::Action;
+  use crate::{Model, run_bridge};
+
+  const WAIT: Duration = Duration::from_secs(5);
+
+  /// Stand in for a real signal account. Hands out whatever gets pushed into `inbox` as incoming
+  /// messages and keeps every command it gets in `sent` instead of actually sending anything.
+  /// Nobody is in its contacts or groups.
+  pub struct FakeMessenger {
+    pub sent: Rc<RefCell<Vec<Cmd>>>,
+    pub inbox: mpsc::UnboundedSender<Received>,
+    incoming: mpsc::UnboundedReceiver<Received>,
+  }
+
+  impl FakeMessenger {
+    /// The script comes first, followed by a `QueueEmpty` like a real sync would
+    pub fn new(script: Vec<Received>) -> Self {
+      let (inbox, incoming) = mpsc::unbounded_channel();
+      for received in script {
+        _ = inbox.send(received);
+      }
+      _ = inbox.send(Received::QueueEmpty);
+
+      Self {
+        sent: Default::default(),
+        inbox,
+        incoming,
+      }
+    }
+  }
+
+  /// Short human readable version of a command, the fake logs these since nothing actually goes out
+  fn describe(cmd: &Cmd) -> String {
+    match cmd {
+      Cmd::SendToGroup { message, .. } => format!("send to group: {}", message),
+      Cmd::SendToThread { thread, message, .. } => format!("send to {:?}: {}", thread, message),
+      Cmd::Send { uuid, message, .. } => format!("send to {}: {}", uuid, message),
+      Cmd::ReactToThread {
+        reaction,
+        target_timestamp,
+        ..
+      } => format!("react {} to {}", reaction, target_timestamp),
+      Cmd::SyncContacts => "sync contacts".to_string(),
+      _ => "some other command".to_string(),
+    }
+  }
+
+  impl Messenger for FakeMessenger {
+    fn spawn(&self, cmd: Cmd) {
+      debug!(cmd = %describe(&cmd), "fake signal");
+      self.sent.borrow_mut().push(cmd);
+    }
+
+    async fn next_message(&mut self) -> Option<Received> {
+      self.incoming.recv().await
+    }
+
+    async fn list_contacts(&self) -> anyhow::Result<Vec<Contact>> {
+      Ok(vec![])
+    }
+
+    async fn list_groups(&self) -> Vec<(GroupMasterKeyBytes, Group)> {
+      vec![]
+    }
+
+    async fn retrieve_profile(&self, uuid: Uuid, _profile_key: Option<ProfileKey>) -> anyhow::Result<Profile> {
+      Err(anyhow::anyhow!("no profile for {}", uuid))
+    }
+
+    async fn shutdown(self) {}
+  }
+
+  /// A bridge on `port = "mock"`, with templates that show exactly who a message came from
+  fn config() -> Config {
+    let mut config = test_config();
+    config.radios[0].port = "mock".to_string();
+    config.templates.mesh_to_signal = "{name} ({short}, {id}) on {channel}: {body}".to_string();
+    config.templates.signal_to_mesh = "{body}".to_string();
+    config
+  }
+
+  /// A group message from `sender` like presage hands them over
+  fn group_text(config: &Config, sender: Uuid, timestamp: u64, body: &str) -> Received {
+    let metadata = Metadata {
+      sender: ServiceId::Aci(sender.into()),
+      destination: ServiceId::Aci(Uuid::nil().into()),
+      sender_device: DeviceId::new(1).unwrap(),
+      timestamp,
+      needs_receipt: false,
+      unidentified_sender: false,
+      was_plaintext: false,
+      server_guid: None,
+    };
+    let message = DataMessage {
+      body: Some(body.to_string()),
+      timestamp: Some(timestamp),
+      group_v2: Some(GroupContextV2 {
+        master_key: Some(config.group_key.to_vec()),
+        revision: Some(1),
+        group_change: None,
+      }),
+      ..Default::default()
+    };
+    Received::Content(Box::new(Content {
+      metadata,
+      body: ContentBody::DataMessage(message),
+    }))
+  }
+
+  /// Checks every so often until `check` comes up with something, the bridge runs in between
+  async fn eventually<T>(mut check: impl FnMut() -> Option<T>) -> T {
+    timeout(WAIT, async {
+      loop {
+        if let Some(found) = check() {
+          return found;
+        }
+        sleep(Duration::from_millis(10)).await;
+      }
+    })
+    .await
+    .expect("gave up waiting on the bridge")
+  }
+
+  /// The mock radio the bridge connected to, once its asked for the config. Anything injected
+  /// after that lands behind the handshake, like it would on a real radio
+  async fn configured_radio() -> MockRadioHandle {
+    let mut radio = eventually(take_connected).await;
+    loop {
+      let to_radio = timeout(WAIT, radio.sent.recv()).await.unwrap().unwrap();
+      if let Some(to_radio::PayloadVariant::WantConfigId(_)) = to_radio.payload_variant {
+        return radio;
+      }
+    }
+  }
+
+  /// The next text the bridge put on the mesh, skipping the admin and nodeinfo traffic
+  async fn next_text(radio: &mut MockRadioHandle) -> (MeshPacket, String) {
+    loop {
+      let to_radio = timeout(WAIT, radio.sent.recv()).await.unwrap().unwrap();
+      let Some(to_radio::PayloadVariant::Packet(packet)) = to_radio.payload_variant else {
+        continue;
+      };
+      if let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant
+        && data.portnum() == PortNum::TextMessageApp
+      {
+        let text = String::from_utf8(data.payload.clone()).unwrap();
+        return (packet, text);
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn mesh_text_reaches_the_group() {
+    LocalSet::new()
+      .run_until(async {
+        let config = config();
+        let messenger = FakeMessenger::new(vec![]);
+        let sent = messenger.sent.clone();
+        let (action_tx, action_rx) = mpsc::unbounded_channel();
+        let quit = action_tx.clone();
+
+        let drive = async {
+          let radio = configured_radio().await;
+          // someone on the public primary, which is neither a dm for us nor the bridged channel
+          radio.inject_text(0xa11c, 0, None, "@alice psst");
+          radio.inject_text(0xa11c, 1, None, "hello from the mesh");
+          eventually(|| (!sent.borrow().is_empty()).then_some(())).await;
+          _ = quit.send(Action::Quit);
+        };
+        let (result, ()) = tokio::join!(
+          run_bridge(&config, Model::init(Uuid::nil()), messenger, action_tx, action_rx),
+          drive
+        );
+        result.unwrap();
+
+        match sent.borrow().as_slice() {
+          [
+            Cmd::SendToGroup {
+              message, master_key, ..
+            },
+          ] => {
+            assert_eq!(*master_key, config.group_key);
+            assert_eq!(
+              message,
+              "Alice's Radio (ALIC, !0000a11c) on gateway: hello from the mesh"
+            );
+          }
+          other => panic!("expected one group send, got {}", other.len()),
+        }
+      })
+      .await;
+  }
+
+  #[tokio::test]
+  async fn signal_text_reaches_the_mesh() {
+    LocalSet::new()
+      .run_until(async {
+        let config = config();
+        let messenger = FakeMessenger::new(vec![]);
+        let sent = messenger.sent.clone();
+        let inbox = messenger.inbox.clone();
+        let (action_tx, action_rx) = mpsc::unbounded_channel();
+        let quit = action_tx.clone();
+        let sender = Uuid::from_u128(0x5167_a1);
+        let timestamp = 1_700_000_000_000;
+
+        let drive = async {
+          let mut radio = configured_radio().await;
+          // a pong coming back means the handshake is done and the radio is ready to send through
+          radio.inject_text(0xa11c, 1, None, "/ping");
+          assert_eq!(next_text(&mut radio).await.1, "pong!");
+
+          _ = inbox.send(group_text(&config, sender, timestamp, "hello from signal"));
+          let (packet, text) = next_text(&mut radio).await;
+          assert_eq!(text, "hello from signal");
+          assert_eq!(packet.channel, 1);
+          assert!(packet.want_ack);
+
+          // the mock acks it, which turns into a reaction on the original message
+          eventually(|| {
+            sent
+              .borrow()
+              .iter()
+              .any(|cmd| {
+                matches!(cmd, Cmd::ReactToThread {
+                  reaction,
+                  target_timestamp,
+                  author_uuid: Some(author),
+                  ..
+                } if reaction == "✔️" && *target_timestamp == timestamp && *author == sender)
+              })
+              .then_some(())
+          })
+          .await;
+          _ = quit.send(Action::Quit);
+        };
+        let (result, ()) = tokio::join!(
+          run_bridge(&config, Model::init(Uuid::nil()), messenger, action_tx, action_rx),
+          drive
+        );
+        result.unwrap();
+
+        // the reaction is all that went to signal
+        assert_eq!(sent.borrow().len(), 1);
+      })
+      .await;
+  }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+use chrono::Utc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::spawn_local;
+use tracing::{debug, error, info};
+
+/// Everything we expose on `/metrics`. Its a global so the router and the signal task can
+/// bump counters without having to pass it all over the place.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+const NODES_HEARD_WINDOW_SECS: i64 = 60 * 60;
+
+#[derive(Default)]
+pub struct Metrics {
+  pub mesh_to_signal: AtomicU64,
+  pub signal_to_mesh: AtomicU64,
+  pub acks: AtomicU64,
+  pub outbox_depth: AtomicU64,
+  pub radio_reconnects: AtomicU64,
+  pub radio_connected: AtomicBool,
+  pub signal_send_errors: AtomicU64,
+  /// unix seconds, 0 means we havent heard anything yet
+  last_packet: AtomicI64,
+  routing_errors: Mutex<HashMap<String, u64>>,
+  /// node num -> unix seconds we last heard from it, only the last hour is kept
+  nodes_heard: Mutex<HashMap<u32, i64>>,
+}
+
+pub fn inc(counter: &AtomicU64) {
+  counter.fetch_add(1, Ordering::Relaxed);
+}
+
+impl Metrics {
+  pub fn routing_error(&self, reason: &str) {
+    let mut errors = self.routing_errors.lock().unwrap();
+    *errors.entry(reason.to_string()).or_default() += 1;
+  }
+
+  pub fn heard_packet(&self) {
+    self.last_packet.store(Utc::now().timestamp(), Ordering::Relaxed);
+  }
+
+  pub fn heard_node(&self, node: u32, when: i64) {
+    let mut nodes = self.nodes_heard.lock().unwrap();
+    let last = nodes.entry(node).or_default();
+    *last = (*last).max(when);
+    // nothing older than the gauge looks at, or a busy mesh grows this forever
+    let now = Utc::now().timestamp();
+    nodes.retain(|_, last| now - *last < NODES_HEARD_WINDOW_SECS);
+  }
+
+  pub fn set_outbox_depth(&self, depth: usize) {
+    self.outbox_depth.store(depth as u64, Ordering::Relaxed);
+  }
+
+  fn render(&self) -> String {
+    let now = Utc::now().timestamp();
+    let mut out = String::new();
+
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>| {
+      _ = writeln!(out, "# HELP mesh2signal_{name} {help}");
+      _ = writeln!(out, "# TYPE mesh2signal_{name} {kind}");
+      for (labels, value) in samples {
+        _ = writeln!(out, "mesh2signal_{name}{labels} {value}");
+      }
+    };
+
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed) as f64;
+
+    metric(
+      "messages_bridged_total",
+      "counter",
+      "Messages bridged between signal and the mesh",
+      vec![
+        (
+          r#"{direction="mesh_to_signal"}"#.to_string(),
+          load(&self.mesh_to_signal),
+        ),
+        (
+          r#"{direction="signal_to_mesh"}"#.to_string(),
+          load(&self.signal_to_mesh),
+        ),
+      ],
+    );
+    metric(
+      "acks_total",
+      "counter",
+      "Mesh packets that got acked",
+      vec![(String::new(), load(&self.acks))],
+    );
+    metric(
+      "routing_errors_total",
+      "counter",
+      "Mesh packets that failed, by routing error",
+      self
+        .routing_errors
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(reason, count)| (format!(r#"{{reason="{reason}"}}"#), *count as f64))
+        .collect(),
+    );
+    metric(
+      "outbox_depth",
+      "gauge",
+      "Messages waiting for the radio",
+      vec![(String::new(), load(&self.outbox_depth))],
+    );
+    metric(
+      "nodes_heard_last_hour",
+      "gauge",
+      "Nodes we heard from in the last hour",
+      vec![(
+        String::new(),
+        self
+          .nodes_heard
+          .lock()
+          .unwrap()
+          .values()
+          .filter(|last| now - **last < NODES_HEARD_WINDOW_SECS)
+          .count() as f64,
+      )],
+    );
+    metric(
+      "radio_reconnects_total",
+      "counter",
+      "Times the radio came back after being lost",
+      vec![(String::new(), load(&self.radio_reconnects))],
+    );
+    metric(
+      "radio_connected",
+      "gauge",
+      "Whether every radio is currently connected",
+      vec![(String::new(), self.radio_connected.load(Ordering::Relaxed) as u8 as f64)],
+    );
+    metric(
+      "signal_send_errors_total",
+      "counter",
+      "Signal sends that failed",
+      vec![(String::new(), load(&self.signal_send_errors))],
+    );
+
+    let last_packet = self.last_packet.load(Ordering::Relaxed);
+    let age = if last_packet == 0 {
+      f64::NAN
+    } else {
+      (now - last_packet) as f64
+    };
+    metric(
+      "last_packet_age_seconds",
+      "gauge",
+      "Seconds since the radio last gave us anything",
+      vec![(String::new(), age)],
+    );
+
+    out
+  }
+}
+
+/// Serves `/metrics` on the given address. Its about as dumb as an http server gets but
+/// prometheus doesnt ask for much.
+pub async fn serve(listen: &str) -> anyhow::Result<()> {
+  let addr: SocketAddr = listen.parse()?;
+  let listener = TcpListener::bind(addr).await?;
+  info!(%addr, "serving metrics");
+
+  spawn_local(async move {
+    loop {
+      match listener.accept().await {
+        Ok((stream, peer)) => {
+          spawn_local(async move {
+            if let Err(err) = respond(stream).await {
+              debug!(%peer, %err, "metrics request failed");
+            }
+          });
+        }
+        Err(err) => {
+          error!(%err, "metrics listener died");
+          break;
+        }
+      }
+    }
+  });
+
+  Ok(())
+}
+
+async fn respond(mut stream: TcpStream) -> anyhow::Result<()> {
+  let mut buf = [0; 1024];
+  let read = stream.read(&mut buf).await?;
+  let request = String::from_utf8_lossy(&buf[..read]);
+
+  let response = if request.starts_with("GET /metrics") {
+    let body = METRICS.render();
+    format!(
+      "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+      body.len(),
+      body
+    )
+  } else {
+    // anyone can poke the port, so this stays out of the normal logs
+    debug!(request = %request.lines().next().unwrap_or_default(), "unknown metrics request");
+    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+  };
+
+  stream.write_all(response.as_bytes()).await?;
+  stream.shutdown().await?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn render_has_help_type_and_counts() {
+    let metrics = Metrics::default();
+    inc(&metrics.mesh_to_signal);
+    inc(&metrics.mesh_to_signal);
+    inc(&metrics.acks);
+    metrics.routing_error("NO_RESPONSE");
+    metrics.set_outbox_depth(3);
+
+    let out = metrics.render();
+    let lines: Vec<&str> = out.lines().collect();
+    for line in [
+      "# HELP mesh2signal_messages_bridged_total Messages bridged between signal and the mesh",
+      "# TYPE mesh2signal_messages_bridged_total counter",
+      r#"mesh2signal_messages_bridged_total{direction="mesh_to_signal"} 2"#,
+      r#"mesh2signal_messages_bridged_total{direction="signal_to_mesh"} 0"#,
+      "# TYPE mesh2signal_acks_total counter",
+      "mesh2signal_acks_total 1",
+      r#"mesh2signal_routing_errors_total{reason="NO_RESPONSE"} 1"#,
+      "# TYPE mesh2signal_outbox_depth gauge",
+      "mesh2signal_outbox_depth 3",
+      "mesh2signal_radio_connected 0",
+      "mesh2signal_last_packet_age_seconds NaN",
+    ] {
+      assert!(lines.contains(&line), "missing {:?} in:\n{}", line, out);
+    }
+    // every metric gets both header lines
+    let help = lines.iter().filter(|line| line.starts_with("# HELP")).count();
+    let kind = lines.iter().filter(|line| line.starts_with("# TYPE")).count();
+    assert_eq!((help, kind), (9, 9));
+  }
+}
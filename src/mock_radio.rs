@@ -0,0 +1,524 @@
+use std::cell::RefCell;
+
+use meshtastic::Message;
+use meshtastic::protobufs::{
+  AdminMessage, Channel, ChannelSettings, Config, Data, DeviceMetadata, FromRadio, MeshPacket, MyNodeInfo, NodeInfo,
+  PortNum, Routing, ToRadio, User, admin_message, channel, config, from_radio, mesh_packet, routing, to_radio,
+};
+use meshtastic::utils::stream::StreamHandle;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, WriteHalf};
+use tokio::sync::mpsc;
+use tokio::task::spawn_local;
+use tracing::{debug, info, warn};
+
+use crate::capture::{encode_frame, next_frame};
+use crate::meshy::BROADCAST;
+
+/// What the fake radio tells the bridge about itself during the configure handshake
+#[derive(Debug, Clone)]
+pub struct MockScript {
+  pub my_node_num: u32,
+  pub firmware_version: String,
+  pub channels: Vec<Channel>,
+  pub lora: config::LoRaConfig,
+  pub nodes: Vec<NodeInfo>,
+  /// answer every `want_ack` packet with a successful routing ack
+  pub auto_ack: bool,
+}
+
+impl Default for MockScript {
+  fn default() -> Self {
+    let my_node_num = 0x4d4f434b;
+
+    Self {
+      my_node_num,
+      firmware_version: "2.6.0.mock".to_string(),
+      channels: vec![
+        mock_channel(0, "", channel::Role::Primary),
+        mock_channel(1, "gateway", channel::Role::Secondary),
+      ],
+      lora: config::LoRaConfig {
+        use_preset: true,
+        modem_preset: config::lo_ra_config::ModemPreset::LongFast.into(),
+        region: config::lo_ra_config::RegionCode::Us.into(),
+        hop_limit: 3,
+        tx_enabled: true,
+        ..Default::default()
+      },
+      nodes: vec![
+        mock_node(my_node_num, "Mock Gateway", "MOCK"),
+        mock_node(0x0000a11c, "Alice's Radio", "ALIC"),
+      ],
+      auto_ack: true,
+    }
+  }
+}
+
+pub fn mock_channel(index: i32, name: &str, role: channel::Role) -> Channel {
+  Channel {
+    index,
+    settings: Some(ChannelSettings {
+      // the default key, same as a fresh radio
+      psk: vec![1],
+      name: name.to_string(),
+      ..Default::default()
+    }),
+    role: role.into(),
+  }
+}
+
+pub fn mock_node(num: u32, long_name: &str, short_name: &str) -> NodeInfo {
+  NodeInfo {
+    num,
+    user: Some(User {
+      id: format!("!{:08x}", num),
+      long_name: long_name.to_string(),
+      short_name: short_name.to_string(),
+      ..Default::default()
+    }),
+    ..Default::default()
+  }
+}
+
+/// Our end of the fake radio. Anything the bridge writes shows up on `sent`, and anything passed
+/// to the `inject_*` functions gets read by the bridge like it came off the serial port.
+pub struct MockRadioHandle {
+  pub my_node_num: u32,
+  inject: mpsc::UnboundedSender<FromRadio>,
+  pub sent: mpsc::UnboundedReceiver<ToRadio>,
+}
+
+impl MockRadioHandle {
+  pub fn inject(&self, packet: FromRadio) {
+    _ = self.inject.send(packet);
+  }
+
+  pub fn inject_mesh_packet(&self, packet: MeshPacket) {
+    self.inject(FromRadio {
+      payload_variant: Some(from_radio::PayloadVariant::Packet(packet)),
+      ..Default::default()
+    });
+  }
+
+  /// Pretend `from` said something, either on a channel (`to` = None) or as a DM to us
+  pub fn inject_text(&self, from: u32, channel: u32, to: Option<u32>, text: &str) {
+    self.inject_mesh_packet(MeshPacket {
+      from,
+      to: to.unwrap_or(BROADCAST),
+      channel,
+      id: rand_id(),
+      payload_variant: Some(mesh_packet::PayloadVariant::Decoded(Data {
+        portnum: PortNum::TextMessageApp.into(),
+        payload: text.as_bytes().to_vec(),
+        ..Default::default()
+      })),
+      ..Default::default()
+    });
+  }
+
+  /// Routing response for a packet we sent, `routing::Error::None` being a successful ack
+  pub fn inject_ack(&self, request_id: u32, from: u32, error: routing::Error) {
+    self.inject_mesh_packet(routing_ack(self.my_node_num, request_id, from, error));
+  }
+}
+
+fn rand_id() -> u32 {
+  meshtastic::utils::generate_rand_id()
+}
+
+fn routing_ack(my_node_num: u32, request_id: u32, from: u32, error: routing::Error) -> MeshPacket {
+  let routing = Routing {
+    variant: Some(routing::Variant::ErrorReason(error.into())),
+  };
+
+  MeshPacket {
+    from,
+    to: my_node_num,
+    id: rand_id(),
+    payload_variant: Some(mesh_packet::PayloadVariant::Decoded(Data {
+      portnum: PortNum::RoutingApp.into(),
+      payload: routing.encode_to_vec(),
+      request_id,
+      ..Default::default()
+    })),
+    ..Default::default()
+  }
+}
+
+/// Spins up a fake radio on an in memory pipe. The returned stream goes to
+/// `StreamApi::connect` in place of the serial stream.
+pub fn spawn_mock_radio(script: MockScript) -> (StreamHandle<DuplexStream>, MockRadioHandle) {
+  let (bridge_end, radio_end) = tokio::io::duplex(64 * 1024);
+  let (inject_tx, inject_rx) = mpsc::unbounded_channel();
+  let (sent_tx, sent_rx) = mpsc::unbounded_channel();
+
+  let handle = MockRadioHandle {
+    my_node_num: script.my_node_num,
+    inject: inject_tx,
+    sent: sent_rx,
+  };
+
+  spawn_local(run_mock_radio(script, radio_end, inject_rx, sent_tx));
+
+  (StreamHandle::from_stream(bridge_end), handle)
+}
+
+async fn run_mock_radio(
+  mut script: MockScript,
+  stream: DuplexStream,
+  mut inject: mpsc::UnboundedReceiver<FromRadio>,
+  sent: mpsc::UnboundedSender<ToRadio>,
+) {
+  let (mut reader, mut writer) = tokio::io::split(stream);
+  let mut buffer = Vec::new();
+  let mut chunk = [0; 1024];
+
+  loop {
+    tokio::select! {
+      read = reader.read(&mut chunk) => {
+        let read = match read {
+          Ok(0) | Err(_) => break,
+          Ok(read) => read,
+        };
+        buffer.extend_from_slice(&chunk[..read]);
+
+        while let Some(frame) = next_frame(&mut buffer) {
+          let to_radio = match ToRadio::decode(frame.as_slice()) {
+            Ok(to_radio) => to_radio,
+            Err(err) => {
+              warn!(%err, "mock radio got a frame it couldnt decode");
+              continue;
+            }
+          };
+
+          for reply in respond(&mut script, &to_radio) {
+            if write_frame(&mut writer, &reply).await.is_err() {
+              return;
+            }
+          }
+
+          _ = sent.send(to_radio);
+        }
+      }
+
+      Some(packet) = inject.recv() => {
+        if write_frame(&mut writer, &packet).await.is_err() {
+          break;
+        }
+      }
+    }
+  }
+
+  info!("mock radio shut down");
+}
+
+async fn write_frame(writer: &mut WriteHalf<DuplexStream>, packet: &FromRadio) -> std::io::Result<()> {
+  writer.write_all(&encode_frame(&packet.encode_to_vec())).await
+}
+
+/// What a real radio would send back for a given `ToRadio`, admin changes stick around so
+/// the bridge sees them when it asks again
+fn respond(script: &mut MockScript, to_radio: &ToRadio) -> Vec<FromRadio> {
+  let from_radio = |variant| FromRadio {
+    payload_variant: Some(variant),
+    ..Default::default()
+  };
+
+  match &to_radio.payload_variant {
+    Some(to_radio::PayloadVariant::WantConfigId(config_id)) => {
+      debug!(config_id, "mock radio answering configure");
+      let mut replies = vec![
+        from_radio(from_radio::PayloadVariant::MyInfo(MyNodeInfo {
+          my_node_num: script.my_node_num,
+          ..Default::default()
+        })),
+        from_radio(from_radio::PayloadVariant::Metadata(DeviceMetadata {
+          firmware_version: script.firmware_version.clone(),
+          ..Default::default()
+        })),
+      ];
+      for node in &script.nodes {
+        replies.push(from_radio(from_radio::PayloadVariant::NodeInfo(node.clone())));
+      }
+      for channel in &script.channels {
+        replies.push(from_radio(from_radio::PayloadVariant::Channel(channel.clone())));
+      }
+      replies.push(from_radio(from_radio::PayloadVariant::Config(Config {
+        payload_variant: Some(config::PayloadVariant::Lora(script.lora.clone())),
+      })));
+      replies.push(from_radio(from_radio::PayloadVariant::ConfigCompleteId(*config_id)));
+      replies
+    }
+
+    Some(to_radio::PayloadVariant::Packet(packet)) if packet.want_ack && script.auto_ack => {
+      // broadcasts get "acked" by the radio itself, dms by whoever they were for
+      let from = if packet.to == BROADCAST {
+        script.my_node_num
+      } else {
+        packet.to
+      };
+      vec![from_radio(from_radio::PayloadVariant::Packet(routing_ack(
+        script.my_node_num,
+        packet.id,
+        from,
+        routing::Error::None,
+      )))]
+    }
+
+    Some(to_radio::PayloadVariant::Packet(packet)) => match admin_request(packet) {
+      Some(admin_message::PayloadVariant::GetChannelRequest(slot)) => {
+        let index = slot as i32 - 1;
+        let channel = script
+          .channels
+          .iter()
+          .find(|channel| channel.index == index)
+          .cloned()
+          .unwrap_or(Channel {
+            index,
+            ..Default::default()
+          });
+        let response = admin_message::PayloadVariant::GetChannelResponse(channel);
+        vec![from_radio(from_radio::PayloadVariant::Packet(admin_response(
+          script.my_node_num,
+          packet.id,
+          response,
+        )))]
+      }
+      Some(admin_message::PayloadVariant::SetChannel(channel)) => {
+        info!(
+          index = channel.index,
+          role = channel.role,
+          "mock radio changing channel"
+        );
+        script.channels.retain(|existing| existing.index != channel.index);
+        script.channels.push(channel);
+        script.channels.sort_by_key(|channel| channel.index);
+        vec![]
+      }
+      _ => vec![],
+    },
+
+    _ => vec![],
+  }
+}
+
+fn admin_request(packet: &MeshPacket) -> Option<admin_message::PayloadVariant> {
+  let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant else {
+    return None;
+  };
+  if data.portnum() != PortNum::AdminApp {
+    return None;
+  }
+  AdminMessage::decode(data.payload.as_slice()).ok()?.payload_variant
+}
+
+fn admin_response(my_node_num: u32, request_id: u32, response: admin_message::PayloadVariant) -> MeshPacket {
+  let admin = AdminMessage {
+    payload_variant: Some(response),
+    ..Default::default()
+  };
+  MeshPacket {
+    from: my_node_num,
+    to: my_node_num,
+    id: rand_id(),
+    payload_variant: Some(mesh_packet::PayloadVariant::Decoded(Data {
+      portnum: PortNum::AdminApp.into(),
+      payload: admin.encode_to_vec(),
+      request_id,
+      ..Default::default()
+    })),
+    ..Default::default()
+  }
+}
+
+/// `port = "mock"` in a test config gets a default mock radio, whose handle waits here for the
+/// test to pick it up since the bridge only ever sees the stream
+pub fn connect_mock() -> StreamHandle<DuplexStream> {
+  let (stream, handle) = spawn_mock_radio(MockScript::default());
+  CONNECTED.with(|connected| connected.borrow_mut().push(handle));
+  stream
+}
+
+/// The oldest mock radio `connect_mock` made on this thread that nobody has taken yet
+pub fn take_connected() -> Option<MockRadioHandle> {
+  CONNECTED.with(|connected| {
+    let mut connected = connected.borrow_mut();
+    (!connected.is_empty()).then(|| connected.remove(0))
+  })
+}
+
+thread_local! {
+  static CONNECTED: RefCell<Vec<MockRadioHandle>> = const { RefCell::new(vec![]) };
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use meshtastic::api::StreamApi;
+  use meshtastic::packet::{PacketDestination, PacketRouter};
+  use meshtastic::types::NodeId;
+  use tokio::task::LocalSet;
+  use tokio::time::timeout;
+
+  use super::*;
+  use crate::config::RadioConfig;
+  use crate::radio::{RadioLink, next_packet};
+  use crate::update::Action;
+
+  const WAIT: Duration = Duration::from_secs(5);
+
+  fn decoded(packet: &FromRadio) -> Option<(&MeshPacket, &Data)> {
+    match &packet.payload_variant {
+      Some(from_radio::PayloadVariant::Packet(
+        mesh_packet @ MeshPacket {
+          payload_variant: Some(mesh_packet::PayloadVariant::Decoded(data)),
+          ..
+        },
+      )) => Some((mesh_packet, data)),
+      _ => None,
+    }
+  }
+
+  #[tokio::test]
+  async fn handshake_text_and_ack() {
+    // the mock runs on spawn_local
+    LocalSet::new()
+      .run_until(async {
+        let script = MockScript::default();
+        let (stream, mut radio) = spawn_mock_radio(script.clone());
+        let (mut listener, api) = StreamApi::new().connect(stream).await;
+        let api = api.configure(1234).await.unwrap();
+
+        // the configure handshake, up to the radio saying its done
+        let mut my_node_num = None;
+        let mut channels = 0;
+        loop {
+          let packet = timeout(WAIT, listener.recv()).await.unwrap().unwrap();
+          match packet.payload_variant {
+            Some(from_radio::PayloadVariant::MyInfo(info)) => my_node_num = Some(info.my_node_num),
+            Some(from_radio::PayloadVariant::Channel(_)) => channels += 1,
+            Some(from_radio::PayloadVariant::ConfigCompleteId(id)) => {
+              assert_eq!(id, 1234);
+              break;
+            }
+            _ => {}
+          }
+        }
+        assert_eq!(my_node_num, Some(script.my_node_num));
+        assert_eq!(channels, script.channels.len());
+
+        let (action_tx, mut actions) = mpsc::unbounded_channel();
+        let mut link = RadioLink::new(&RadioConfig::default(), action_tx);
+        link.connected(listener, api);
+        link.router.set_id(NodeId::new(script.my_node_num));
+
+        // a text packet in
+        radio.inject_text(0xa11c, 1, None, "hello mesh");
+        let (index, packet) = timeout(WAIT, next_packet(std::slice::from_mut(&mut link)))
+          .await
+          .unwrap();
+        assert_eq!(index, 0);
+        let packet = packet.unwrap();
+        let (mesh_packet, data) = decoded(&packet).unwrap();
+        assert_eq!(mesh_packet.from, 0xa11c);
+        assert_eq!(mesh_packet.to, BROADCAST);
+        assert_eq!(data.portnum(), PortNum::TextMessageApp);
+        assert_eq!(data.payload, b"hello mesh");
+
+        // a text packet out, which the mock acks
+        let id = link
+          .send(
+            b"hello signal".to_vec(),
+            PortNum::TextMessageApp,
+            PacketDestination::Broadcast,
+            1.into(),
+            true,
+            false,
+          )
+          .await
+          .unwrap()
+          .unwrap();
+        let sent = loop {
+          let to_radio = timeout(WAIT, radio.sent.recv()).await.unwrap().unwrap();
+          if let Some(to_radio::PayloadVariant::Packet(packet)) = to_radio.payload_variant {
+            break packet;
+          }
+        };
+        assert_eq!(sent.id, id);
+        assert!(sent.want_ack);
+
+        let (_, ack) = timeout(WAIT, next_packet(std::slice::from_mut(&mut link)))
+          .await
+          .unwrap();
+        let ack = ack.unwrap();
+        let (_, data) = decoded(&ack).unwrap();
+        assert_eq!(data.portnum(), PortNum::RoutingApp);
+        assert_eq!(data.request_id, id);
+
+        // and the router matches it up with what we sent
+        link.router.handle_packet_from_radio(ack).unwrap();
+        match actions.try_recv() {
+          Ok(Action::MeshAck { packet, deliverd }) => {
+            assert_eq!(packet.id, id);
+            assert!(deliverd);
+          }
+          other => panic!("expected an ack, got {:?}", other.map(|_| ())),
+        }
+      })
+      .await;
+  }
+
+  #[tokio::test]
+  async fn failed_ack() {
+    LocalSet::new()
+      .run_until(async {
+        let script = MockScript {
+          auto_ack: false,
+          ..Default::default()
+        };
+        let (stream, radio) = spawn_mock_radio(script.clone());
+        let (listener, api) = StreamApi::new().connect(stream).await;
+        let api = api.configure(1).await.unwrap();
+
+        let (action_tx, mut actions) = mpsc::unbounded_channel();
+        let mut link = RadioLink::new(&RadioConfig::default(), action_tx);
+        link.connected(listener, api);
+        link.router.set_id(NodeId::new(script.my_node_num));
+
+        let id = link
+          .send(
+            b"anyone?".to_vec(),
+            PortNum::TextMessageApp,
+            PacketDestination::Node(0xa11c.into()),
+            0.into(),
+            true,
+            false,
+          )
+          .await
+          .unwrap()
+          .unwrap();
+        radio.inject_ack(id, 0xa11c, routing::Error::MaxRetransmit);
+
+        // the handshake comes first, the ack is the one routing packet
+        loop {
+          let (_, packet) = timeout(WAIT, next_packet(std::slice::from_mut(&mut link)))
+            .await
+            .unwrap();
+          let packet = packet.unwrap();
+          if decoded(&packet).is_some_and(|(_, data)| data.portnum() == PortNum::RoutingApp) {
+            link.router.handle_packet_from_radio(packet).unwrap();
+            break;
+          }
+        }
+        match actions.try_recv() {
+          Ok(Action::MeshAck { packet, deliverd }) => {
+            assert_eq!(packet.id, id);
+            assert!(!deliverd);
+          }
+          other => panic!("expected an ack, got {:?}", other.map(|_| ())),
+        }
+      })
+      .await;
+  }
+}
@@ -6,13 +6,14 @@ use tokio;
 use tokio::sync::mpsc;
 // use tokio::task::LocalSet;
 use tokio::sync::oneshot;
-use tokio::task::spawn_local;
+use tokio::task::{JoinHandle, spawn_local};
 
 use crate::Profile;
 use crate::ProfileKey;
 use crate::Received;
 use crate::Uuid;
-use crate::logger::Logger;
+use crate::messenger::Messenger;
+use crate::metrics::{METRICS, inc};
 use crate::signal::Cmd;
 use crate::signal::attachments_tmp_dir;
 use crate::signal::get_contacts;
@@ -25,6 +26,7 @@ use crate::update::Action;
 use futures::StreamExt;
 use futures::pin_mut;
 use tokio::select;
+use tracing::{debug, error, info};
 
 use crate::MyManager;
 use presage::Error;
@@ -49,9 +51,11 @@ struct ProfileRequest {
 
 pub struct SignalSpawner {
   send: mpsc::UnboundedSender<Cmd>,
+  incoming: mpsc::UnboundedReceiver<Received>,
   contact_requests: Requester<Result<Vec<Contact>, Error<SqliteStoreError>>>,
   group_requests: Requester<Vec<(GroupMasterKeyBytes, Group)>>,
   profile_requests: mpsc::UnboundedSender<ProfileRequest>,
+  task: JoinHandle<()>,
 }
 
 impl SignalSpawner {
@@ -99,10 +103,11 @@ impl SignalSpawner {
       mpsc::unbounded_channel::<oneshot::Sender<Vec<(GroupMasterKeyBytes, Group)>>>();
 
     let (profile_sender, mut profile_requests) = mpsc::unbounded_channel();
+    let (incoming_tx, incoming) = mpsc::unbounded_channel();
 
     // let (message_tx, mut message_rx) = mpsc::unbounded_channel();
 
-    spawn_local(async move {
+    let task = spawn_local(async move {
       // initialize message stream
       let messages = manager
         .receive_messages()
@@ -124,8 +129,9 @@ impl SignalSpawner {
 
       // let mut counter;
 
-      // should enable some gracefull shutdown
-      while !output.is_closed() && !recv.is_closed() {
+      // the command channel closing is handled below, `is_closed` would bail before the
+      // leftover commands get drained
+      while !output.is_closed() {
         // which we can only wait and see if this was a bad choice
 
         select! {
@@ -152,34 +158,51 @@ impl SignalSpawner {
           Some(content) = messages.next() => {
             match &content {
               Received::QueueEmpty => {
-                println!("nothing left");
-                _ = output.send(Action::Receive(Received::QueueEmpty));
+                debug!("signal queue empty");
                 // break;
               }
               Received::Contacts => {
-                println!("got contacts synchronization");
+                info!("got contacts synchronization");
               }
               Received::Content(content) => {
-                Logger::log("mhhhh some juicy content for you");
+                debug!(timestamp = content.metadata.timestamp, sender = %content.metadata.sender.raw_uuid(), "received signal content");
                 // this better be fast lmao
                 process_incoming_message(&mut manager, attachments_tmp_dir.path(), false, &content).await
               }
             }
 
-            _ = output.send(Action::Receive(content));
+            _ = incoming_tx.send(content);
 
           }
 
-          Some(task) = recv.recv() => {
-            _ = run(&mut manager, task, output.clone()).await;
-            // if counter > max_commands_in_a_row {
-            //   break;
-            // }
+          task = recv.recv() => match task {
+            Some(task) => {
+              // only failed sends count for the metric, the rest are just lookups
+              let sending = matches!(
+                task,
+                Cmd::Send { .. }
+                  | Cmd::SendToGroup { .. }
+                  | Cmd::SendToThread { .. }
+                  | Cmd::EditMessage { .. }
+                  | Cmd::ReactToThread { .. }
+              );
+              if let Err(err) = run(&mut manager, task, output.clone()).await {
+                if sending {
+                  inc(&METRICS.signal_send_errors);
+                }
+                error!(%err, "signal command failed");
+              }
+              // if counter > max_commands_in_a_row {
+              //   break;
+              // }
+            }
+            // the spawner is gone and everything it queued has been sent, so were done here
+            None => break,
           }
         }
       }
 
-      Logger::log("gracefully shutdown ... (hopefully)".to_string());
+      info!("signal task shut down");
 
       // while let Some(new_task) = recv.recv().await {
       //   // Logger::log(format!("we gyatt a message but before"));
@@ -200,9 +223,11 @@ impl SignalSpawner {
 
     Self {
       send: send,
+      incoming,
       contact_requests: contacts_sender,
       profile_requests: profile_sender,
       group_requests: groups_sender,
+      task,
     }
   }
 
@@ -224,26 +249,32 @@ impl SignalSpawner {
   //   // objects have been dropped.
   // }
 
-  pub fn spawn(&self, task: Cmd) {
+  pub fn sync_contacts(&self) {
+    _ = self.send.send(Cmd::SyncContacts);
+  }
+}
+
+impl Messenger for SignalSpawner {
+  fn spawn(&self, task: Cmd) {
     self
       .send
       .send(task)
       .expect("Thread with LocalSet has shut down.");
   }
 
-  pub async fn list_contacts(&self) -> Result<Vec<Contact>, Error<SqliteStoreError>> {
+  async fn next_message(&mut self) -> Option<Received> {
+    self.incoming.recv().await
+  }
+
+  async fn list_contacts(&self) -> anyhow::Result<Vec<Contact>> {
     let (tx, rx) = oneshot::channel();
 
     _ = self.contact_requests.send(tx);
 
-    return rx.await.expect("kaboom");
+    Ok(rx.await.expect("kaboom")?)
   }
 
-  pub async fn retrieve_profile(
-    &self,
-    uuid: Uuid,
-    profile_key: Option<ProfileKey>,
-  ) -> anyhow::Result<Profile> {
+  async fn retrieve_profile(&self, uuid: Uuid, profile_key: Option<ProfileKey>) -> anyhow::Result<Profile> {
     let (tx, rx) = oneshot::channel();
 
     _ = self.profile_requests.send(ProfileRequest {
@@ -252,11 +283,10 @@ impl SignalSpawner {
       profile_key,
     });
 
-    Logger::log("awaiting...");
     return rx.await.expect("kaboom");
   }
 
-  pub async fn list_groups(&self) -> Vec<(GroupMasterKeyBytes, Group)> {
+  async fn list_groups(&self) -> Vec<(GroupMasterKeyBytes, Group)> {
     let (tx, rx) = oneshot::channel();
 
     _ = self.group_requests.send(tx);
@@ -264,8 +294,15 @@ impl SignalSpawner {
     return rx.await.expect("kaboom once again");
   }
 
-  pub fn sync_contacts(&self) {
-    _ = self.send.send(Cmd::SyncContacts);
+  /// Stops taking commands and waits for the local task to work through whatever is
+  /// still queued (mainly sends) before it exits
+  async fn shutdown(self) {
+    let Self { send, task, .. } = self;
+    drop(send);
+
+    if let Err(err) = task.await {
+      error!(%err, "signal task died on the way out");
+    }
   }
 }
 
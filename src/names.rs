@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use presage::model::contacts::Contact;
+use tracing::debug;
+
+use crate::config::Config;
+use crate::messenger::Messenger;
+use crate::{Model, Profile, Uuid};
+
+/// What the contact list told us about someone, for when their profile doesnt pan out
+#[derive(Debug)]
+struct ContactFallback {
+  name: String,
+  phone: Option<String>,
+}
+
+/// Display names for signal senders. Group members usually arent contacts, so their names
+/// come from fetching profiles, which is slow enough that we only do it once in a while.
+#[derive(Debug)]
+pub struct SenderNames {
+  ttl: Duration,
+  cache: HashMap<Uuid, (String, Instant)>,
+  contacts: HashMap<Uuid, ContactFallback>,
+}
+
+impl SenderNames {
+  pub fn new(ttl: Duration) -> Self {
+    Self {
+      ttl,
+      cache: HashMap::new(),
+      contacts: HashMap::new(),
+    }
+  }
+
+  pub fn remember_contact(&mut self, contact: &Contact) {
+    self.contacts.insert(
+      contact.uuid,
+      ContactFallback {
+        name: contact.name.trim().to_string(),
+        phone: contact.phone_number.as_ref().map(|phone| phone.to_string()),
+      },
+    );
+  }
+
+  fn is_fresh(&self, uuid: &Uuid) -> bool {
+    self
+      .cache
+      .get(uuid)
+      .is_some_and(|(_, fetched)| fetched.elapsed() < self.ttl)
+  }
+
+  /// Contact name, then the last few digits of their number, then we give up
+  fn fallback(&self, uuid: &Uuid) -> String {
+    match self.contacts.get(uuid) {
+      Some(ContactFallback { name, .. }) if !name.is_empty() => name.clone(),
+      Some(ContactFallback { phone: Some(phone), .. }) if phone.len() >= 4 => {
+        format!("…{}", &phone[phone.len() - 4..])
+      }
+      _ => "Unknown".to_string(),
+    }
+  }
+
+  /// Every name we have for anyone, some people show up more than once
+  fn known(&self) -> impl Iterator<Item = (Uuid, &str)> {
+    self.cache.iter().map(|(uuid, (name, _))| (*uuid, name.as_str())).chain(
+      self
+        .contacts
+        .iter()
+        .map(|(uuid, contact)| (*uuid, contact.name.as_str())),
+    )
+  }
+
+  /// Whatever we have on hand, a stale name still beats "Unknown"
+  pub fn get(&self, uuid: &Uuid) -> String {
+    match self.cache.get(uuid) {
+      Some((name, _)) => name.clone(),
+      None => self.fallback(uuid),
+    }
+  }
+}
+
+pub fn given_name(profile: &Profile) -> Option<String> {
+  let name = profile.name.as_ref()?.given_name.trim();
+  (!name.is_empty()).then(|| name.to_string())
+}
+
+fn full_name(profile: &Profile) -> Option<String> {
+  let name = profile.name.as_ref()?;
+  let full = match &name.family_name {
+    Some(family) => format!("{} {}", name.given_name, family),
+    None => name.given_name.clone(),
+  };
+  let full = full.trim();
+  (!full.is_empty()).then(|| full.to_string())
+}
+
+/// Levenshtein, names are short enough that the simple version is fine
+fn edit_distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut row: Vec<usize> = (0..=b.len()).collect();
+  for (i, a) in a.chars().enumerate() {
+    let mut diagonal = row[0];
+    row[0] = i + 1;
+    for (j, b) in b.iter().enumerate() {
+      let above = row[j + 1];
+      row[j + 1] = if a == *b {
+        diagonal
+      } else {
+        1 + diagonal.min(above).min(row[j])
+      };
+      diagonal = above;
+    }
+  }
+  row[b.len()]
+}
+
+/// How well a (lowercased) query fits a name, 0 for not at all
+fn name_score(query: &str, name: &str) -> u8 {
+  let name = name.to_lowercase();
+  let words: Vec<&str> = name.split_whitespace().collect();
+  if name == query {
+    4
+  } else if words.contains(&query) {
+    3
+  } else if name.starts_with(query) || words.iter().any(|word| word.starts_with(query)) {
+    2
+  } else if query.chars().count() >= 4 && words.iter().any(|word| edit_distance(word, query) <= 1) {
+    // typos from a tiny keyboard in the field
+    1
+  } else {
+    0
+  }
+}
+
+/// The signal people whose names fit `query` best, with the fullest name we have for each.
+/// Underscores and dots stand in for spaces, so "@alice_smith" can pick between two alices.
+pub fn find_people(model: &Model, query: &str) -> Vec<(Uuid, String)> {
+  let mut names: HashMap<Uuid, Vec<String>> = HashMap::new();
+  for (uuid, name) in model.names.known() {
+    names.entry(uuid).or_default().push(name.to_string());
+  }
+  for (uuid, profile) in model.contacts.iter() {
+    if let Some(name) = full_name(profile) {
+      names.entry(*uuid).or_default().push(name);
+    }
+  }
+  names.remove(&model.account.uuid);
+
+  let query = query.to_lowercase().replace(['_', '.'], " ");
+  let mut best = 0;
+  let mut matches = vec![];
+  for (uuid, names) in names {
+    let score = names.iter().map(|name| name_score(&query, name)).max().unwrap_or(0);
+    if score == 0 || score < best {
+      continue;
+    }
+    if score > best {
+      best = score;
+      matches.clear();
+    }
+    let fullest = names.into_iter().max_by_key(|name| name.len()).unwrap_or_default();
+    matches.push((uuid, fullest));
+  }
+  matches.sort_by(|a, b| a.1.cmp(&b.1));
+  matches
+}
+
+/// Makes sure `model.names` has something reasonably fresh for `uuid`, going to the signal
+/// servers for the profile if we have to. Needs to happen before `handle_message`, which cant wait.
+pub async fn resolve_sender(model: &mut Model, config: &Config, messenger: &impl Messenger, uuid: Uuid) {
+  if model.names.is_fresh(&uuid) {
+    return;
+  }
+
+  // contacts get their profiles fetched up front
+  let mut name = model.contacts.get(&uuid).and_then(given_name);
+
+  if name.is_none() {
+    let profile_key = model
+      .groups
+      .get(&config.group_key)
+      .and_then(|group| group.members.iter().find(|member| Uuid::from(member.aci) == uuid))
+      .map(|member| member.profile_key);
+
+    match messenger.retrieve_profile(uuid, profile_key).await {
+      Ok(profile) => name = given_name(&profile),
+      Err(err) => debug!(%uuid, %err, "couldnt get profile for sender"),
+    }
+  }
+
+  let name = name.unwrap_or_else(|| model.names.fallback(&uuid));
+  debug!(%uuid, %name, "resolved sender name");
+  model.names.cache.insert(uuid, (name, Instant::now()));
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use presage::libsignal_service::profile_name::ProfileName;
+
+  use super::*;
+  use crate::config::test_config;
+  use crate::messenger::tests::FakeMessenger;
+
+  fn uuid(n: u128) -> Uuid {
+    Uuid::from_u128(n)
+  }
+
+  fn profile(given_name: &str, family_name: Option<&str>) -> Profile {
+    Profile {
+      name: Some(ProfileName {
+        given_name: given_name.to_string(),
+        family_name: family_name.map(str::to_string),
+      }),
+      about: None,
+      about_emoji: None,
+      avatar: None,
+      unrestricted_unidentified_access: false,
+    }
+  }
+
+  fn fallback(names: &mut SenderNames, uuid: Uuid, name: &str, phone: Option<&str>) {
+    names.contacts.insert(
+      uuid,
+      ContactFallback {
+        name: name.to_string(),
+        phone: phone.map(str::to_string),
+      },
+    );
+  }
+
+  #[test]
+  fn contact_name_then_phone_then_unknown() {
+    let mut names = SenderNames::new(Duration::from_secs(60));
+    fallback(&mut names, uuid(1), "Alice", Some("+15551234567"));
+    fallback(&mut names, uuid(2), "", Some("+15551234567"));
+    fallback(&mut names, uuid(3), "", Some("123"));
+    fallback(&mut names, uuid(4), "", Some("1234"));
+    fallback(&mut names, uuid(5), "", None);
+
+    assert_eq!(names.get(&uuid(1)), "Alice");
+    assert_eq!(names.get(&uuid(2)), "…4567");
+    // too short to be worth showing
+    assert_eq!(names.get(&uuid(3)), "Unknown");
+    assert_eq!(names.get(&uuid(4)), "…1234");
+    assert_eq!(names.get(&uuid(5)), "Unknown");
+    assert_eq!(names.get(&uuid(6)), "Unknown");
+  }
+
+  #[test]
+  fn blank_profile_names_dont_count() {
+    assert_eq!(given_name(&profile("  Bob ", None)).as_deref(), Some("Bob"));
+    assert_eq!(given_name(&profile("   ", Some("Smith"))), None);
+    assert_eq!(full_name(&profile("   ", Some("Smith"))).as_deref(), Some("Smith"));
+    assert_eq!(full_name(&profile("", None)), None);
+  }
+
+  #[tokio::test]
+  async fn profile_name_beats_the_contact_list() {
+    let config = test_config();
+    let messenger = FakeMessenger::new(vec![]);
+    let mut model = Model::init(uuid(100));
+    model.contacts = Arc::new(HashMap::from([
+      (uuid(1), profile("Carol", None)),
+      (uuid(2), profile(" ", None)),
+    ]));
+    fallback(&mut model.names, uuid(1), "Caz", None);
+    fallback(&mut model.names, uuid(2), "Dave", None);
+
+    for n in 1..=3 {
+      resolve_sender(&mut model, &config, &messenger, uuid(n)).await;
+    }
+    assert_eq!(model.names.get(&uuid(1)), "Carol");
+    // the fake has no profiles to hand out, so its the contact list next
+    assert_eq!(model.names.get(&uuid(2)), "Dave");
+    assert_eq!(model.names.get(&uuid(3)), "Unknown");
+  }
+
+  #[test]
+  fn edit_distance_counts_single_edits() {
+    assert_eq!(edit_distance("alice", "alice"), 0);
+    assert_eq!(edit_distance("alice", "alise"), 1);
+    assert_eq!(edit_distance("alice", "alic"), 1);
+    assert_eq!(edit_distance("alice", "aalice"), 1);
+    // swapped letters are two edits
+    assert_eq!(edit_distance("alice", "alcie"), 2);
+    assert_eq!(edit_distance("", "bob"), 3);
+    assert_eq!(edit_distance("zoë", "zoe"), 1);
+  }
+
+  #[test]
+  fn find_people_keeps_every_best_match() {
+    let mut model = Model::init(uuid(100));
+    let now = Instant::now();
+    model.names.cache.insert(uuid(1), ("Alice".to_string(), now));
+    model.names.cache.insert(uuid(2), ("Alice".to_string(), now));
+    model.names.cache.insert(uuid(3), ("Alicia".to_string(), now));
+    model.names.cache.insert(uuid(100), ("Alice".to_string(), now));
+    model.contacts = Arc::new(HashMap::from([
+      (uuid(1), profile("Alice", Some("Smith"))),
+      (uuid(2), profile("Alice", Some("Jones"))),
+    ]));
+
+    // both alices fit exactly and alicia only by prefix, we never find ourselves
+    let found = find_people(&model, "alice");
+    assert_eq!(
+      found,
+      vec![
+        (uuid(2), "Alice Jones".to_string()),
+        (uuid(1), "Alice Smith".to_string())
+      ]
+    );
+    assert_eq!(
+      find_people(&model, "Alice_Smith"),
+      vec![(uuid(1), "Alice Smith".to_string())]
+    );
+    assert_eq!(find_people(&model, "ali").len(), 3);
+    assert_eq!(find_people(&model, "smitt"), vec![(uuid(1), "Alice Smith".to_string())]);
+    assert!(find_people(&model, "bob").is_empty());
+  }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::Utc;
+use meshtastic::packet::PacketDestination;
+use meshtastic::protobufs::HardwareModel;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+use crate::Model;
+use crate::config::Config;
+use crate::meshy::{mesh_sender_name, parse_node_id};
+use crate::signal::config_dir_path;
+use crate::status::{format_age, radio_label};
+use crate::store_forward::{load_toml, save_toml};
+use crate::update::Action;
+
+/// What we remember about a node between runs, so a restart doesnt make everything new again
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WatchedNode {
+  pub node: u32,
+  /// unix seconds
+  pub last_heard: i64,
+  #[serde(default)]
+  pub hops: Option<u32>,
+  /// the group already heard it went quiet
+  #[serde(default)]
+  pub quiet: bool,
+  /// the node asked not to be announced
+  #[serde(default)]
+  pub opted_out: bool,
+}
+
+// same table at the top as the pairings
+#[derive(Default, Deserialize, Serialize)]
+struct NodeWatchFile {
+  #[serde(default)]
+  nodes: Vec<WatchedNode>,
+}
+
+/// How long hearing nodes can go unsaved. a crash loses at most this much of last heard times
+const SAVE_EVERY: Duration = Duration::from_secs(5 * 60);
+
+/// How hearing a node compares to the last time
+#[derive(Debug, PartialEq, Eq)]
+pub enum Heard {
+  First,
+  /// after this long without a peep
+  Back(Duration),
+  Again,
+}
+
+/// Every node weve ever heard and when, for telling the group about the comings and goings
+#[derive(Debug, Default)]
+pub struct NodeWatch {
+  nodes: HashMap<u32, WatchedNode>,
+  /// when something first changed that isnt on disk yet
+  unsaved_since: Option<Instant>,
+}
+
+fn node_watch_path() -> String {
+  let mut dir = config_dir_path();
+  dir.push_str("nodes_seen.toml");
+  dir
+}
+
+fn ignored(config: &Config, node: u32) -> bool {
+  config
+    .node_notices
+    .ignore
+    .iter()
+    .any(|id| parse_node_id(id) == Some(node))
+}
+
+impl NodeWatch {
+  pub fn load() -> Self {
+    let path = node_watch_path();
+    let nodes = match load_toml::<NodeWatchFile>(&path) {
+      Ok(Some(file)) => file.nodes,
+      // first run
+      Ok(None) => vec![],
+      Err(err) => {
+        error!(%err, %path, "seen nodes file is unreadable, starting over");
+        vec![]
+      }
+    };
+
+    info!(nodes = nodes.len(), "loaded seen nodes");
+    Self {
+      nodes: nodes.into_iter().map(|watched| (watched.node, watched)).collect(),
+      unsaved_since: None,
+    }
+  }
+
+  pub fn save(&mut self) {
+    self.unsaved_since = None;
+    let file = NodeWatchFile {
+      nodes: self.nodes.values().cloned().collect(),
+    };
+    if let Err(err) = save_toml(&node_watch_path(), &file) {
+      warn!(%err, "failed to save seen nodes");
+    }
+  }
+
+  /// When whatever weve heard since the last save should hit the disk
+  pub fn save_deadline(&self) -> Option<Instant> {
+    self.unsaved_since.map(|since| since + SAVE_EVERY)
+  }
+
+  pub fn contains(&self, node: u32) -> bool {
+    self.nodes.contains_key(&node)
+  }
+
+  /// Nodes the radio already knew about dont get announced as new. `last_heard` is what the
+  /// radio says, anything already quiet for long enough is taken as already announced
+  pub fn seed(&mut self, config: &Config, node: u32, last_heard: u32) {
+    let now = Utc::now().timestamp();
+    let last_heard = if last_heard == 0 { now } else { last_heard as i64 };
+    let quiet_after = config.node_notices.quiet_after_minutes as i64 * 60;
+    self.nodes.entry(node).or_insert(WatchedNode {
+      node,
+      last_heard,
+      hops: None,
+      quiet: quiet_after != 0 && now - last_heard >= quiet_after,
+      opted_out: false,
+    });
+  }
+
+  pub fn heard(&mut self, config: &Config, node: u32, hops: Option<u32>) -> Heard {
+    let now = Utc::now().timestamp();
+    // saved on a timer, a busy mesh would have us writing the file for every packet otherwise
+    self.unsaved_since.get_or_insert_with(Instant::now);
+    let Some(watched) = self.nodes.get_mut(&node) else {
+      self.nodes.insert(
+        node,
+        WatchedNode {
+          node,
+          last_heard: now,
+          hops,
+          quiet: false,
+          opted_out: false,
+        },
+      );
+      return Heard::First;
+    };
+
+    let silence = Duration::from_secs(now.saturating_sub(watched.last_heard).max(0) as u64);
+    let back_after = Duration::from_secs(config.node_notices.back_after_minutes * 60);
+    let heard = if watched.quiet || silence >= back_after {
+      Heard::Back(silence)
+    } else {
+      Heard::Again
+    };
+    watched.last_heard = now;
+    watched.quiet = false;
+    if hops.is_some() {
+      watched.hops = hops;
+    }
+    heard
+  }
+
+  pub fn opted_out(&self, node: u32) -> bool {
+    self.nodes.get(&node).is_some_and(|watched| watched.opted_out)
+  }
+
+  /// Whether the node was known at all
+  pub fn set_opted_out(&mut self, node: u32, opted_out: bool) -> bool {
+    let Some(watched) = self.nodes.get_mut(&node) else {
+      return false;
+    };
+    watched.opted_out = opted_out;
+    self.save();
+    true
+  }
+
+  fn watching(config: &Config, watched: &WatchedNode) -> bool {
+    !watched.quiet && !watched.opted_out && !ignored(config, watched.node)
+  }
+
+  /// When the next node goes quiet, if were telling anyone about that
+  pub fn next_deadline(&self, config: &Config) -> Option<Instant> {
+    let quiet_after = config.node_notices.quiet_after_minutes as i64 * 60;
+    if !config.node_notices.enabled || quiet_after == 0 {
+      return None;
+    }
+    let due = self
+      .nodes
+      .values()
+      .filter(|watched| Self::watching(config, watched))
+      .map(|watched| watched.last_heard + quiet_after)
+      .min()?;
+    let wait = (due - Utc::now().timestamp()).max(0) as u64;
+    Some(Instant::now() + Duration::from_secs(wait))
+  }
+
+  /// Nodes that just crossed the quiet threshold, with how long theyve been gone
+  pub fn gone_quiet(&mut self, config: &Config) -> Vec<(u32, Duration, Option<u32>)> {
+    let now = Utc::now().timestamp();
+    let quiet_after = config.node_notices.quiet_after_minutes as i64 * 60;
+    let mut quiet = vec![];
+    for watched in self.nodes.values_mut() {
+      if Self::watching(config, watched) && now - watched.last_heard >= quiet_after {
+        watched.quiet = true;
+        quiet.push((
+          watched.node,
+          Duration::from_secs((now - watched.last_heard) as u64),
+          watched.hops,
+        ));
+      }
+    }
+    if !quiet.is_empty() {
+      self.save();
+    }
+    quiet
+  }
+}
+
+fn format_hops(hops: Option<u32>) -> String {
+  match hops {
+    Some(0) => "direct".to_string(),
+    Some(1) => "1 hop".to_string(),
+    Some(hops) => format!("{} hops", hops),
+    None => "hops unknown".to_string(),
+  }
+}
+
+/// "Alice's T-Beam (TBEAM, 2 hops)", from whichever radio knows the node best
+fn describe(model: &Model, config: &Config, node: u32, hops: Option<u32>) -> String {
+  let state = model
+    .radios
+    .iter()
+    .find(|state| state.nodes.get(&node).is_some_and(|info| info.user.is_some()))
+    .unwrap_or(&model.radios[0]);
+  let name = mesh_sender_name(&state.nodes, node, config.mesh.name_display);
+  let hardware = state
+    .nodes
+    .get(&node)
+    .and_then(|info| info.user.as_ref())
+    .map(|user| user.hw_model())
+    .filter(|hardware| *hardware != HardwareModel::Unset)
+    .map_or("unknown hardware", |hardware| hardware.as_str_name());
+  format!("{} ({}, {})", name, hardware, format_hops(hops))
+}
+
+fn to_group(config: &Config, message: String) -> Action {
+  Action::SendToGroup {
+    message,
+    ranges: vec![],
+    master_key: config.group_key,
+  }
+}
+
+/// A node the radio already had in its node list, from when it connected
+pub fn node_known(model: &mut Model, config: &Config, node: u32, last_heard: u32) {
+  if model.radios.iter().any(|state| state.my_node_num == Some(node)) {
+    return;
+  }
+  model.node_watch.seed(config, node, last_heard);
+}
+
+/// Any packet from a node. Tells the group if its new or back after a while
+pub fn node_heard(model: &mut Model, config: &Config, radio: usize, node: u32, hops: Option<u32>) -> Option<Action> {
+  if !config.node_notices.enabled || model.radios.iter().any(|state| state.my_node_num == Some(node)) {
+    return None;
+  }
+  // a brand new node waits until weve got its name, which we ask it for when we hear it
+  let named = model.radios[radio]
+    .nodes
+    .get(&node)
+    .is_some_and(|info| info.user.is_some());
+  if !named && !model.node_watch.contains(node) {
+    return None;
+  }
+
+  let heard = model.node_watch.heard(config, node, hops);
+  if heard == Heard::Again || model.node_watch.opted_out(node) || ignored(config, node) {
+    return None;
+  }
+
+  let mut message = match heard {
+    Heard::First => format!("📡 new node: {}", describe(model, config, node, hops)),
+    Heard::Back(silence) => format!(
+      "📡 {} is back after {}",
+      describe(model, config, node, hops),
+      format_age(silence)
+    ),
+    Heard::Again => unreachable!("returned above"),
+  };
+  if config.radios.len() > 1 {
+    message.push_str(&format!(", heard by {}", radio_label(config, radio)));
+  }
+  info!(node, ?heard, "announcing node");
+  Some(to_group(config, message))
+}
+
+/// Whoever went quiet since we last looked, all in one message
+pub fn quiet_nodes(model: &mut Model, config: &Config) -> Option<Action> {
+  let quiet = model.node_watch.gone_quiet(config);
+  if quiet.is_empty() {
+    return None;
+  }
+  info!(nodes = quiet.len(), "announcing quiet nodes");
+  let model = &*model;
+  let lines: Vec<String> = quiet
+    .into_iter()
+    .map(|(node, silence, hops)| {
+      format!(
+        "📴 {} quiet for {}",
+        describe(model, config, node, hops),
+        format_age(silence)
+      )
+    })
+    .collect();
+  Some(to_group(config, lines.join("\n")))
+}
+
+/// `/announce on|off` dmed from a node, for people who dont want their comings and goings posted
+pub fn mesh_announce(model: &mut Model, radio: usize, node: u32, args: &str) -> Action {
+  let body = match args {
+    "on" | "off" => {
+      let opted_out = args == "off";
+      if model.node_watch.set_opted_out(node, opted_out) {
+        info!(node, opted_out, "node changed its announcements");
+        if opted_out {
+          "ok, signal wont hear when this node comes or goes".to_string()
+        } else {
+          "ok, signal will hear when this node comes or goes".to_string()
+        }
+      } else {
+        "havent heard this node properly yet, try again in a bit".to_string()
+      }
+    }
+    _ => "usage: /announce on|off".to_string(),
+  };
+  Action::SendToMesh {
+    radio,
+    body,
+    channel: 0.into(),
+    destination: PacketDestination::Node(node.into()),
+    signal_message: None,
+    outbox_id: None,
+  }
+}
@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use meshtastic::packet::PacketDestination;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::admin::is_admin;
+use crate::config::Config;
+use crate::meshy::parse_node_id;
+use crate::signal::config_dir_path;
+use crate::status::node_display_name;
+use crate::store_forward::{load_toml, save_toml};
+use crate::update::{Action, reply_to_thread};
+use crate::{Model, Thread, Uuid};
+
+/// How long a `/link` code is good for
+pub const LINK_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// wrong codes one node or account gets per `LINK_TIMEOUT` before we stop listening to it
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+/// wrong codes from anyone on the other side before a code is thrown out. theres a million
+/// codes, so this keeps guessing hopeless even for someone making up node ids as they go
+const MAX_WRONG_GUESSES: u32 = 20;
+
+/// A mesh node that someone proved is theirs by getting a code from one side to the other
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Pairing {
+  pub node: u32,
+  pub uuid: Uuid,
+  /// unix seconds
+  pub linked_at: i64,
+}
+
+// toml needs a table at the top, same as the outbox
+#[derive(Default, Deserialize, Serialize)]
+struct PairingsFile {
+  #[serde(default)]
+  pairs: Vec<Pairing>,
+}
+
+/// Whoever asked for a code, the other side has to be the one that enters it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LinkSide {
+  /// a node, and the radio it asked through so we can tell it how it went
+  Mesh {
+    radio: usize,
+    node: u32,
+  },
+  Signal(Uuid),
+}
+
+impl LinkSide {
+  /// A node is the same node whichever radio heard it
+  fn entrant(self) -> LinkSide {
+    match self {
+      LinkSide::Mesh { node, .. } => LinkSide::Mesh { radio: 0, node },
+      signal => signal,
+    }
+  }
+
+  fn opposite(self, other: LinkSide) -> bool {
+    matches!(
+      (self, other),
+      (LinkSide::Mesh { .. }, LinkSide::Signal(_)) | (LinkSide::Signal(_), LinkSide::Mesh { .. })
+    )
+  }
+}
+
+#[derive(Debug)]
+struct PendingLink {
+  code: String,
+  issued_at: Instant,
+  side: LinkSide,
+  /// wrong codes entered from the other side while this one was waiting, any of them could
+  /// have been a guess at it
+  wrong_guesses: u32,
+}
+
+/// Verified mesh node <-> signal account pairs, saved to disk on every change
+#[derive(Debug, Default)]
+pub struct Pairings {
+  pairs: Vec<Pairing>,
+  pending: Vec<PendingLink>,
+  /// wrong codes per node or account, and since when
+  failures: HashMap<LinkSide, (u32, Instant)>,
+}
+
+fn pairings_path() -> String {
+  let mut dir = config_dir_path();
+  dir.push_str("pairings.toml");
+  dir
+}
+
+impl Pairings {
+  pub fn load() -> Self {
+    let path = pairings_path();
+    let pairs = match load_toml::<PairingsFile>(&path) {
+      Ok(Some(file)) => file.pairs,
+      // nobody has linked anything yet
+      Ok(None) => vec![],
+      Err(err) => {
+        error!(%err, %path, "pairings file is unreadable, starting without any");
+        vec![]
+      }
+    };
+
+    if !pairs.is_empty() {
+      info!(pairs = pairs.len(), "loaded linked nodes");
+    }
+    Self {
+      pairs,
+      ..Default::default()
+    }
+  }
+
+  /// Whoever owns this node, if anyone proved it
+  pub fn uuid_for(&self, node: u32) -> Option<Uuid> {
+    self.pairs.iter().find(|pair| pair.node == node).map(|pair| pair.uuid)
+  }
+
+  /// People do end up with more than one radio
+  pub fn nodes_for(&self, uuid: &Uuid) -> Vec<u32> {
+    self
+      .pairs
+      .iter()
+      .filter(|pair| pair.uuid == *uuid)
+      .map(|pair| pair.node)
+      .collect()
+  }
+
+  /// A fresh code for one side to hand to the other, replacing any it already had
+  pub fn start(&mut self, side: LinkSide) -> String {
+    self.expire();
+    self.pending.retain(|pending| pending.side != side);
+
+    // every code belongs to exactly one request
+    let code = loop {
+      let code = format!("{:06}", meshtastic::utils::generate_rand_id::<u32>() % 1_000_000);
+      if !self.pending.iter().any(|pending| pending.code == code) {
+        break code;
+      }
+    };
+    self.pending.push(PendingLink {
+      code: code.clone(),
+      issued_at: Instant::now(),
+      side,
+      wrong_guesses: 0,
+    });
+    code
+  }
+
+  /// A node entering a code it got from signal, hands back who that was
+  pub fn complete_from_mesh(&mut self, radio: usize, node: u32, code: &str) -> Result<Uuid, String> {
+    match self.complete(LinkSide::Mesh { radio, node }, code)? {
+      LinkSide::Signal(uuid) => Ok(uuid),
+      LinkSide::Mesh { .. } => unreachable!("codes only pair opposite sides"),
+    }
+  }
+
+  /// Someone on signal entering a code a node got, hands back the radio and node
+  pub fn complete_from_signal(&mut self, uuid: Uuid, code: &str) -> Result<(usize, u32), String> {
+    match self.complete(LinkSide::Signal(uuid), code)? {
+      LinkSide::Mesh { radio, node } => Ok((radio, node)),
+      LinkSide::Signal(_) => unreachable!("codes only pair opposite sides"),
+    }
+  }
+
+  /// The other side entering a code. Hands back whoever asked for it, now that theyre paired
+  fn complete(&mut self, side: LinkSide, code: &str) -> Result<LinkSide, String> {
+    self.expire();
+    let entrant = side.entrant();
+    if self
+      .failures
+      .get(&entrant)
+      .is_some_and(|(count, _)| *count >= MAX_FAILED_ATTEMPTS)
+    {
+      warn!(?entrant, "ignoring link code from someone with too many wrong ones");
+      return Err("too many wrong codes, try again later".to_string());
+    }
+
+    let Some(index) = self
+      .pending
+      .iter()
+      .position(|pending| pending.code == code.trim() && side.opposite(pending.side))
+    else {
+      self.wrong_code(side);
+      return Err("that code doesnt match anything, it might have expired".to_string());
+    };
+    let other = self.pending.remove(index).side;
+    self.failures.remove(&entrant);
+
+    let (node, uuid) = match (side, other) {
+      (LinkSide::Mesh { node, .. }, LinkSide::Signal(uuid)) | (LinkSide::Signal(uuid), LinkSide::Mesh { node, .. }) => {
+        (node, uuid)
+      }
+      _ => unreachable!("checked above"),
+    };
+
+    // a node only has the one owner
+    self.pairs.retain(|pair| pair.node != node);
+    self.pairs.push(Pairing {
+      node,
+      uuid,
+      linked_at: Utc::now().timestamp(),
+    });
+    info!(node, %uuid, "linked node");
+    self.persist();
+    Ok(other)
+  }
+
+  pub fn unlink_node(&mut self, node: u32) -> Option<Pairing> {
+    let index = self.pairs.iter().position(|pair| pair.node == node)?;
+    let pair = self.pairs.remove(index);
+    info!(node, uuid = %pair.uuid, "unlinked node");
+    self.persist();
+    Some(pair)
+  }
+
+  /// Counts against whoever entered it, and against every code they could have been guessing
+  fn wrong_code(&mut self, side: LinkSide) {
+    let (count, _) = self.failures.entry(side.entrant()).or_insert((0, Instant::now()));
+    *count += 1;
+
+    for pending in self.pending.iter_mut().filter(|pending| side.opposite(pending.side)) {
+      pending.wrong_guesses += 1;
+    }
+    self.pending.retain(|pending| {
+      let keep = pending.wrong_guesses < MAX_WRONG_GUESSES;
+      if !keep {
+        warn!(side = ?pending.side, "too many wrong guesses, throwing the link code out");
+      }
+      keep
+    });
+  }
+
+  fn expire(&mut self) {
+    self
+      .pending
+      .retain(|pending| pending.issued_at.elapsed() < LINK_TIMEOUT);
+    self.failures.retain(|_, (_, since)| since.elapsed() < LINK_TIMEOUT);
+  }
+
+  fn persist(&self) {
+    let file = PairingsFile {
+      pairs: self.pairs.clone(),
+    };
+    if let Err(err) = save_toml(&pairings_path(), &file) {
+      warn!(%err, "failed to save linked nodes");
+    }
+  }
+}
+
+/// The ids people can dm to reach the gateway, one per radio we know the node of
+fn gateway_nodes(model: &Model) -> String {
+  let nodes: Vec<String> = model
+    .radios
+    .iter()
+    .filter_map(|state| state.my_node_num)
+    .map(|node| format!("!{:08x}", node))
+    .collect();
+  if nodes.is_empty() {
+    "once its back online".to_string()
+  } else {
+    nodes.join(" or ")
+  }
+}
+
+/// Where a node is known from, so we can name it and dm it
+fn find_node(model: &Model, node: u32) -> Option<usize> {
+  model.radios.iter().position(|state| state.nodes.contains_key(&node))
+}
+
+fn mesh_dm(radio: usize, node: u32, body: String) -> Action {
+  Action::SendToMesh {
+    radio,
+    body,
+    channel: 0.into(),
+    destination: PacketDestination::Node(node.into()),
+    signal_message: None,
+    outbox_id: None,
+  }
+}
+
+/// `/link [code]` dmed to the gateway from a node
+pub fn mesh_link(model: &mut Model, radio: usize, node: u32, code: &str) -> Action {
+  if code.is_empty() {
+    let code = model.pairings.start(LinkSide::Mesh { radio, node });
+    return mesh_dm(
+      radio,
+      node,
+      format!(
+        "link code {}. send \"/link {}\" to the signal group or the bridge within {} minutes",
+        code,
+        code,
+        LINK_TIMEOUT.as_secs() / 60
+      ),
+    );
+  }
+
+  match model.pairings.complete_from_mesh(radio, node, code) {
+    Ok(uuid) => {
+      let node_name = node_display_name(&model.radios[radio].nodes, node);
+      Action::Batch(vec![
+        mesh_dm(
+          radio,
+          node,
+          format!("✅ linked to {} on signal", model.names.get(&uuid)),
+        ),
+        Action::SendToContact {
+          uuid,
+          message: format!("✅ {} is now linked to you", node_name),
+        },
+      ])
+    }
+    Err(err) => mesh_dm(radio, node, err),
+  }
+}
+
+/// `/unlink` dmed from a node, which only ever unlinks itself
+pub fn mesh_unlink(model: &mut Model, radio: usize, node: u32) -> Action {
+  match model.pairings.unlink_node(node) {
+    Some(pair) => mesh_dm(radio, node, format!("unlinked from {}", model.names.get(&pair.uuid))),
+    None => mesh_dm(radio, node, "this node isnt linked to anyone".to_string()),
+  }
+}
+
+/// `/link [code]` from signal. The code goes out in a dm so nobody else in the group can claim it
+pub fn signal_link(model: &mut Model, config: &Config, thread: &Thread, uuid: Uuid, code: &str) -> Action {
+  if code.is_empty() {
+    let code = model.pairings.start(LinkSide::Signal(uuid));
+    let direct = Action::SendToContact {
+      uuid,
+      message: format!(
+        "your link code is {}. send \"/link {}\" as a dm to the gateway ({}) from your radio within {} minutes",
+        code,
+        code,
+        gateway_nodes(model),
+        LINK_TIMEOUT.as_secs() / 60
+      ),
+    };
+    return match thread {
+      Thread::Group(_) => Action::Batch(vec![
+        direct,
+        reply_to_thread(config, thread, "sent you a link code in a direct message".to_string()),
+      ]),
+      Thread::Contact(_) => direct,
+    };
+  }
+
+  match model.pairings.complete_from_signal(uuid, code) {
+    Ok((radio, node)) => {
+      let name = model.names.get(&uuid);
+      let node_name = node_display_name(&model.radios[radio].nodes, node);
+      Action::Batch(vec![
+        reply_to_thread(config, thread, format!("✅ {} is now linked to {}", node_name, name)),
+        mesh_dm(radio, node, format!("✅ linked to {} on signal", name)),
+      ])
+    }
+    Err(err) => reply_to_thread(config, thread, err),
+  }
+}
+
+/// `/unlink [!nodeid]` from signal. Everyone can unlink their own nodes, admins can unlink any
+pub fn signal_unlink(model: &mut Model, config: &Config, thread: &Thread, uuid: Uuid, args: &str) -> Action {
+  let nodes = if args.is_empty() {
+    model.pairings.nodes_for(&uuid)
+  } else {
+    match parse_node_id(args) {
+      Some(node) => match model.pairings.uuid_for(node) {
+        Some(owner) if owner == uuid || is_admin(model, config, &uuid) => vec![node],
+        Some(_) => return reply_to_thread(config, thread, "thats not your node to unlink".to_string()),
+        None => vec![],
+      },
+      None => return reply_to_thread(config, thread, "usage: /unlink [!nodeid]".to_string()),
+    }
+  };
+
+  if nodes.is_empty() {
+    return reply_to_thread(config, thread, "nothing to unlink".to_string());
+  }
+
+  let mut actions = vec![];
+  let mut names = vec![];
+  for node in nodes {
+    model.pairings.unlink_node(node);
+    match find_node(model, node) {
+      Some(radio) => {
+        names.push(node_display_name(&model.radios[radio].nodes, node));
+        actions.push(mesh_dm(radio, node, "this node was unlinked from signal".to_string()));
+      }
+      None => names.push(format!("!{:08x}", node)),
+    }
+  }
+  actions.insert(
+    0,
+    reply_to_thread(config, thread, format!("unlinked {}", names.join(", "))),
+  );
+  Action::Batch(actions)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn node(node: u32) -> LinkSide {
+    LinkSide::Mesh { radio: 0, node }
+  }
+
+  #[test]
+  fn too_many_wrong_codes_locks_out_the_sender() {
+    let mut pairings = Pairings::default();
+    let code = pairings.start(LinkSide::Signal(Uuid::nil()));
+    for _ in 0..MAX_FAILED_ATTEMPTS {
+      assert!(pairings.complete(node(1), "nope").is_err());
+    }
+    // even the right one, through another radio
+    let err = pairings
+      .complete(LinkSide::Mesh { radio: 1, node: 1 }, &code)
+      .unwrap_err();
+    assert!(err.contains("too many"));
+    assert_eq!(pairings.pending.len(), 1);
+  }
+
+  #[test]
+  fn code_is_thrown_out_after_enough_guesses() {
+    let mut pairings = Pairings::default();
+    let code = pairings.start(LinkSide::Signal(Uuid::nil()));
+    // a new made up node for every guess so none of them get locked out
+    for guesser in 0..MAX_WRONG_GUESSES {
+      assert!(pairings.complete(node(100 + guesser), "nope").is_err());
+    }
+    assert!(pairings.pending.is_empty());
+    let err = pairings.complete(node(1), &code).unwrap_err();
+    assert!(err.contains("doesnt match"));
+  }
+
+  #[test]
+  fn wrong_guesses_only_count_against_the_other_side() {
+    let mut pairings = Pairings::default();
+    pairings.start(node(1));
+    for _ in 0..MAX_WRONG_GUESSES {
+      assert!(pairings.complete(node(2), "nope").is_err());
+    }
+    assert_eq!(pairings.pending.len(), 1);
+  }
+}
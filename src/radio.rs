@@ -0,0 +1,284 @@
+use std::future::poll_fn;
+use std::task::Poll;
+use std::time::Duration;
+
+use meshtastic::api::{ConnectedStreamApi, StreamApi, state};
+use meshtastic::packet::PacketDestination;
+use meshtastic::protobufs::{FromRadio, PortNum};
+use meshtastic::types::MeshChannel;
+use meshtastic::utils;
+use meshtastic::utils::stream::StreamHandle;
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::Nodes;
+use crate::capture::{CaptureStream, spawn_replay};
+use crate::channels::ChannelTable;
+use crate::config::RadioConfig;
+use crate::dumb_packet_router::DumbPacketRouter;
+use crate::radio_info::RadioInfo;
+use crate::update::Action;
+
+pub type RadioApi = ConnectedStreamApi<state::Configured>;
+
+/// Opens the serial port (or a capture replay) and kicks off the configure
+/// handshake. The node and channel infos trickle in afterwards through the returned listener
+/// like any other `FromRadio` packet.
+pub async fn connect_radio(config: &RadioConfig) -> anyhow::Result<(UnboundedReceiver<FromRadio>, RadioApi)> {
+  info!(port = %config.port, "connecting to radio");
+
+  let (decoded_listener, stream_api) = if let Some(replay) = &config.replay {
+    connect_stream(spawn_replay(replay, config)?, config).await?
+  } else if let Some(stream) = mock_stream(config) {
+    connect_stream(stream, config).await?
+  } else {
+    let serial_stream = utils::stream::build_serial_stream(config.port.clone(), None, None, None)?;
+    connect_stream(serial_stream, config).await?
+  };
+
+  let config_id = utils::generate_rand_id();
+  let stream_api = stream_api.configure(config_id).await?;
+
+  Ok((decoded_listener, stream_api))
+}
+
+/// The tests run the whole bridge against a mock radio with `port = "mock"`
+#[cfg(test)]
+fn mock_stream(config: &RadioConfig) -> Option<StreamHandle<DuplexStream>> {
+  (config.port == "mock").then(crate::mock_radio::connect_mock)
+}
+
+#[cfg(not(test))]
+fn mock_stream(_config: &RadioConfig) -> Option<StreamHandle<DuplexStream>> {
+  None
+}
+
+/// Connects over whatever stream we ended up with, teeing it into the capture file if theres one
+async fn connect_stream<S>(
+  stream: StreamHandle<S>,
+  config: &RadioConfig,
+) -> anyhow::Result<(UnboundedReceiver<FromRadio>, ConnectedStreamApi)>
+where
+  S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+  Ok(match &config.capture {
+    Some(path) => {
+      let stream = CaptureStream::new(stream.stream, path)?;
+      StreamApi::new().connect(StreamHandle::from_stream(stream)).await
+    }
+    None => StreamApi::new().connect(stream).await,
+  })
+}
+
+/// Exponential backoff between reconnect attempts
+pub struct Backoff {
+  min: Duration,
+  max: Duration,
+  current: Duration,
+}
+
+impl Backoff {
+  pub fn new(config: &RadioConfig) -> Self {
+    let min = Duration::from_secs(config.min_backoff_secs);
+    Self {
+      min,
+      max: Duration::from_secs(config.max_backoff_secs),
+      current: min,
+    }
+  }
+
+  /// How long to wait before the next attempt, doubling every time we get asked
+  pub fn next(&mut self) -> Duration {
+    let delay = self.current;
+    self.current = (self.current * 2).min(self.max);
+    delay
+  }
+
+  pub fn reset(&mut self) {
+    self.current = self.min;
+  }
+}
+
+/// What we know about one radio. Lives in the model so the handlers can get at it, the
+/// connection itself is in `RadioLink`
+#[derive(Debug)]
+pub struct RadioState {
+  pub name: String,
+  pub connected: bool,
+  pub my_node_num: Option<u32>,
+  /// what the radio told us about itself while configuring
+  pub info: RadioInfo,
+  pub channels: ChannelTable,
+  pub nodes: Nodes,
+}
+
+impl RadioState {
+  pub fn new(config: &RadioConfig) -> Self {
+    Self {
+      name: config.name.clone(),
+      connected: false,
+      my_node_num: None,
+      info: RadioInfo::default(),
+      channels: ChannelTable::default(),
+      nodes: Nodes::new(),
+    }
+  }
+
+  /// Connected, and sure which node we are. Sending before that would get every packet id and
+  /// ack wrong, so until then everything waits in the outbox.
+  pub fn ready(&self) -> bool {
+    self.connected && self.my_node_num.is_some()
+  }
+
+  /// Forgets everything the radio told us, configure makes it send all of it again and it
+  /// could be a different radio than last time
+  pub fn reset(&mut self) {
+    self.nodes.clear();
+    self.channels.clear();
+    self.info = RadioInfo::default();
+    self.my_node_num = None;
+  }
+}
+
+/// The connection to one radio and the timers that keep it alive
+pub struct RadioLink {
+  pub config: RadioConfig,
+  pub api: Option<RadioApi>,
+  pub listener: Option<UnboundedReceiver<FromRadio>>,
+  pub router: DumbPacketRouter,
+  packet_ids: UnboundedReceiver<u32>,
+  pub backoff: Backoff,
+  pub last_heard: Instant,
+  pub next_reconnect: Instant,
+  next_channel_refresh: Instant,
+  /// only tell the group its back if we told them it was gone
+  pub announced_offline: bool,
+}
+
+impl RadioLink {
+  /// Starts out "disconnected" and lets the reconnect logic do the first connection too, so a
+  /// missing radio at startup doesnt stop the signal side from coming up
+  pub fn new(config: &RadioConfig, action_tx: mpsc::UnboundedSender<Action>) -> Self {
+    let (packet_id_tx, packet_ids) = mpsc::unbounded_channel::<u32>();
+    Self {
+      config: config.clone(),
+      api: None,
+      listener: None,
+      router: DumbPacketRouter::new(action_tx, packet_id_tx),
+      packet_ids,
+      backoff: Backoff::new(config),
+      last_heard: Instant::now(),
+      next_reconnect: Instant::now(),
+      next_channel_refresh: Instant::now(),
+      announced_offline: false,
+    }
+  }
+
+  pub fn connected(&mut self, listener: UnboundedReceiver<FromRadio>, api: RadioApi) {
+    self.listener = Some(listener);
+    self.api = Some(api);
+    self.last_heard = Instant::now();
+    self.next_channel_refresh = Instant::now() + Duration::from_secs(self.config.channel_refresh_secs);
+    self.backoff.reset();
+  }
+
+  /// Drops the connection and schedules the next attempt, handing back the api so it can be
+  /// disconnected properly
+  pub fn lost(&mut self) -> Option<RadioApi> {
+    self.listener = None;
+    let delay = self.backoff.next();
+    warn!(radio = %self.config.name, ?delay, "lost the radio, reconnecting later");
+    self.next_reconnect = Instant::now() + delay;
+    self.api.take()
+  }
+
+  /// Sends one packet and hands back the id the router gave it
+  pub async fn send(
+    &mut self,
+    payload: Vec<u8>,
+    portnum: PortNum,
+    destination: PacketDestination,
+    channel: MeshChannel,
+    want_ack: bool,
+    want_response: bool,
+  ) -> anyhow::Result<Option<u32>> {
+    let Some(api) = self.api.as_mut() else {
+      anyhow::bail!("radio isnt connected");
+    };
+    let result = api
+      .send_mesh_packet(
+        &mut self.router,
+        payload.into(),
+        portnum,
+        destination,
+        channel,
+        want_ack,
+        want_response,
+        // only the ones were waiting on an ack need to come back through the router
+        want_ack,
+        None,
+        None,
+      )
+      .await;
+    // the router hands us the id of every packet on its way out
+    let id = self.packet_ids.try_recv().ok();
+    result.map_err(|err| anyhow::anyhow!("{}", err))?;
+    Ok(id)
+  }
+
+  /// A capture replay plays once and then its over. Gaps in it arent the radio dying, and
+  /// reconnecting would bridge the whole thing all over again
+  pub fn replaying(&self) -> bool {
+    self.config.replay.is_some()
+  }
+
+  /// When this radio next needs looking at. The heartbeat and channel refresh while its
+  /// connected, the next reconnect attempt while its not
+  pub fn next_deadline(&self, running: bool) -> Option<Instant> {
+    if self.api.is_none() {
+      return running.then_some(self.next_reconnect);
+    }
+    let heartbeat = (!self.replaying()).then(|| self.last_heard + Duration::from_secs(self.config.heartbeat_secs));
+    let channel_refresh = (self.config.channel_refresh_secs != 0).then_some(self.next_channel_refresh);
+    heartbeat.into_iter().chain(channel_refresh).min()
+  }
+
+  /// Whichever of the `next_deadline` things is due, if any
+  pub fn due(&mut self, radio: usize, running: bool) -> Option<Action> {
+    let now = Instant::now();
+    if self.api.is_none() {
+      return (running && self.next_reconnect <= now).then_some(Action::ConnectRadio { radio });
+    }
+
+    let heartbeat = Duration::from_secs(self.config.heartbeat_secs);
+    if !self.replaying() && self.last_heard + heartbeat <= now {
+      warn!(radio = %self.config.name, ?heartbeat, "havent heard from the radio, assuming its dead");
+      return Some(Action::RadioLost { radio });
+    }
+
+    let channel_refresh = Duration::from_secs(self.config.channel_refresh_secs);
+    if !channel_refresh.is_zero() && self.next_channel_refresh <= now {
+      self.next_channel_refresh = now + channel_refresh;
+      return Some(Action::RefreshChannels { radio });
+    }
+    None
+  }
+}
+
+/// The next packet from whichever radio has one, `None` meaning that radios connection closed.
+/// Never finishes while no radio is connected.
+pub async fn next_packet(links: &mut [RadioLink]) -> (usize, Option<FromRadio>) {
+  poll_fn(|cx| {
+    for (radio, link) in links.iter_mut().enumerate() {
+      if let Some(listener) = link.listener.as_mut() {
+        if let Poll::Ready(packet) = listener.poll_recv(cx) {
+          return Poll::Ready((radio, packet));
+        }
+      }
+    }
+    Poll::Pending
+  })
+  .await
+}
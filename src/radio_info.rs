@@ -0,0 +1,204 @@
+use meshtastic::protobufs::{
+  Config, DeviceMetadata, LocalConfig, LocalModuleConfig, ModuleConfig, MyNodeInfo, config, module_config,
+};
+
+use crate::Nodes;
+use crate::status::node_display_name;
+
+/// Everything the radio tells us about itself while configuring, kept around for `/radio`
+/// and anything else that cares what kind of radio its talking to
+#[derive(Debug, Default)]
+pub struct RadioInfo {
+  pub my_info: Option<MyNodeInfo>,
+  pub metadata: Option<DeviceMetadata>,
+  pub config: LocalConfig,
+  pub module_config: LocalModuleConfig,
+}
+
+/// "LONG_FAST" -> "LongFast", which is how the apps (and channel names) spell them
+fn camel_case(name: &str) -> String {
+  name
+    .split('_')
+    .map(|word| {
+      let mut chars = word.chars();
+      match chars.next() {
+        Some(first) => first.to_string() + &chars.as_str().to_lowercase(),
+        None => String::new(),
+      }
+    })
+    .collect()
+}
+
+impl RadioInfo {
+  pub fn update_config(&mut self, config: Config) {
+    let Some(variant) = config.payload_variant else {
+      return;
+    };
+    match variant {
+      config::PayloadVariant::Device(device) => self.config.device = Some(device),
+      config::PayloadVariant::Position(position) => self.config.position = Some(position),
+      config::PayloadVariant::Power(power) => self.config.power = Some(power),
+      config::PayloadVariant::Network(network) => self.config.network = Some(network),
+      config::PayloadVariant::Display(display) => self.config.display = Some(display),
+      config::PayloadVariant::Lora(lora) => self.config.lora = Some(lora),
+      config::PayloadVariant::Bluetooth(bluetooth) => self.config.bluetooth = Some(bluetooth),
+      // newer firmware has a few more that we dont care about
+      #[allow(unreachable_patterns)]
+      _ => {}
+    }
+  }
+
+  pub fn update_module_config(&mut self, module: ModuleConfig) {
+    let Some(variant) = module.payload_variant else {
+      return;
+    };
+    let modules = &mut self.module_config;
+    match variant {
+      module_config::PayloadVariant::Mqtt(mqtt) => modules.mqtt = Some(mqtt),
+      module_config::PayloadVariant::Serial(serial) => modules.serial = Some(serial),
+      module_config::PayloadVariant::ExternalNotification(notification) => {
+        modules.external_notification = Some(notification)
+      }
+      module_config::PayloadVariant::StoreForward(store_forward) => modules.store_forward = Some(store_forward),
+      module_config::PayloadVariant::RangeTest(range_test) => modules.range_test = Some(range_test),
+      module_config::PayloadVariant::Telemetry(telemetry) => modules.telemetry = Some(telemetry),
+      module_config::PayloadVariant::CannedMessage(canned) => modules.canned_message = Some(canned),
+      module_config::PayloadVariant::Audio(audio) => modules.audio = Some(audio),
+      module_config::PayloadVariant::RemoteHardware(hardware) => modules.remote_hardware = Some(hardware),
+      module_config::PayloadVariant::NeighborInfo(neighbor_info) => modules.neighbor_info = Some(neighbor_info),
+      module_config::PayloadVariant::AmbientLighting(lighting) => modules.ambient_lighting = Some(lighting),
+      module_config::PayloadVariant::DetectionSensor(sensor) => modules.detection_sensor = Some(sensor),
+      module_config::PayloadVariant::Paxcounter(paxcounter) => modules.paxcounter = Some(paxcounter),
+      // same here
+      #[allow(unreachable_patterns)]
+      _ => {}
+    }
+  }
+
+  pub fn firmware_version(&self) -> Option<&str> {
+    self
+      .metadata
+      .as_ref()
+      .map(|metadata| metadata.firmware_version.as_str())
+  }
+
+  pub fn hardware(&self) -> Option<&'static str> {
+    Some(self.metadata.as_ref()?.hw_model().as_str_name())
+  }
+
+  pub fn role(&self) -> Option<&'static str> {
+    Some(self.config.device.as_ref()?.role().as_str_name())
+  }
+
+  pub fn lora(&self) -> Option<&config::LoRaConfig> {
+    self.config.lora.as_ref()
+  }
+
+  /// What an unnamed channel is called, which the radio bases on the modem preset
+  pub fn preset_name(&self) -> Option<String> {
+    let lora = self.lora()?;
+    Some(if lora.use_preset {
+      camel_case(lora.modem_preset().as_str_name())
+    } else {
+      "Custom".to_string()
+    })
+  }
+
+  /// Things that will quietly stop the bridge from working
+  pub fn warnings(&self) -> Vec<String> {
+    let mut warnings = vec![];
+    if let Some(lora) = self.lora() {
+      if lora.region() == config::lo_ra_config::RegionCode::Unset {
+        warnings.push("no lora region set, the radio wont transmit".to_string());
+      }
+      if !lora.tx_enabled {
+        warnings.push("transmit is turned off".to_string());
+      }
+    }
+    if let Some(device) = &self.config.device {
+      if device.role() == config::device_config::Role::ClientMute {
+        warnings.push("radio is CLIENT_MUTE, it wont rebroadcast anything".to_string());
+      }
+    }
+    warnings
+  }
+
+  fn enabled_modules(&self) -> Vec<&'static str> {
+    let modules = &self.module_config;
+    [
+      ("mqtt", modules.mqtt.as_ref().is_some_and(|module| module.enabled)),
+      ("serial", modules.serial.as_ref().is_some_and(|module| module.enabled)),
+      (
+        "external notification",
+        modules
+          .external_notification
+          .as_ref()
+          .is_some_and(|module| module.enabled),
+      ),
+      (
+        "store & forward",
+        modules.store_forward.as_ref().is_some_and(|module| module.enabled),
+      ),
+      (
+        "range test",
+        modules.range_test.as_ref().is_some_and(|module| module.enabled),
+      ),
+      (
+        "neighbor info",
+        modules.neighbor_info.as_ref().is_some_and(|module| module.enabled),
+      ),
+      (
+        "detection sensor",
+        modules.detection_sensor.as_ref().is_some_and(|module| module.enabled),
+      ),
+      (
+        "paxcounter",
+        modules.paxcounter.as_ref().is_some_and(|module| module.enabled),
+      ),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect()
+  }
+}
+
+/// What `/radio` says about one radio, kept to a handful of lines like `/status`
+pub fn radio_report(title: &str, info: &RadioInfo, nodes: &Nodes) -> String {
+  let mut lines = vec![title.to_string()];
+
+  match &info.my_info {
+    Some(my_info) => lines.push(format!("node: {}", node_display_name(nodes, my_info.my_node_num))),
+    None => return format!("{}\nhavent heard from the radio yet", title),
+  }
+
+  let mut hardware = info.hardware().unwrap_or("unknown hardware").to_string();
+  if let Some(version) = info.firmware_version() {
+    hardware.push_str(&format!(", fw {}", version));
+  }
+  lines.push(format!("hw: {}", hardware));
+
+  if let Some(role) = info.role() {
+    lines.push(format!("role: {}", role));
+  }
+
+  if let Some(lora) = info.lora() {
+    lines.push(format!(
+      "lora: {} {}, hop limit {}, {}dBm",
+      lora.region().as_str_name(),
+      info.preset_name().unwrap_or_default(),
+      lora.hop_limit,
+      lora.tx_power
+    ));
+  }
+
+  let modules = info.enabled_modules();
+  if !modules.is_empty() {
+    lines.push(format!("modules: {}", modules.join(", ")));
+  }
+
+  for warning in info.warnings() {
+    lines.push(format!("⚠️ {}", warning));
+  }
+
+  lines.join("\n")
+}
@@ -0,0 +1,251 @@
+use crate::config::{EmojiMode, ShapingConfig};
+
+// signal puts one of these wherever an @mention goes, the actual name lives in a body range
+const MENTION_PLACEHOLDER: char = '\u{fffc}';
+
+/// Zero width joiners, variation selectors and friends. Invisible, but they still cost bytes.
+fn is_zero_width(c: char) -> bool {
+  matches!(
+    c,
+    '\u{200b}'..='\u{200f}' | '\u{2060}'..='\u{2064}' | '\u{feff}' | '\u{fe00}'..='\u{fe0f}' | '\u{e0020}'..='\u{e007f}'
+  ) || ('\u{1f3fb}'..='\u{1f3ff}').contains(&c) // skin tones
+}
+
+/// The handful of emoji people actually use, in something a radio screen can show
+fn fold_emoji(c: char) -> Option<&'static str> {
+  Some(match c {
+    '👍' => "(y)",
+    '👎' => "(n)",
+    '😀' | '😃' | '😄' | '😁' => ":D",
+    '😂' | '🤣' => "xD",
+    '🙂' | '😊' => ":)",
+    '😉' => ";)",
+    '🙁' | '😞' | '😢' => ":(",
+    '😮' | '😲' => ":O",
+    '😛' | '😜' => ":P",
+    '😐' => ":|",
+    '🙏' => "(pray)",
+    '🔥' => "(fire)",
+    '💯' => "(100)",
+    '🎉' => "(party)",
+    '👋' => "(wave)",
+    '💩' => "(poop)",
+    _ => return None,
+  })
+}
+
+/// Gets a signal message into a shape thats cheap to send over lora. Everything here is about
+/// bytes, not characters, since thats what the payload limit counts.
+pub fn shape(text: &str, config: &ShapingConfig) -> String {
+  let mut shaped = String::with_capacity(text.len());
+
+  for c in text.chars() {
+    if c == MENTION_PLACEHOLDER || (config.strip_zero_width && is_zero_width(c)) {
+      continue;
+    }
+
+    // anything outside the basic multilingual plane is 4 bytes, which is mostly emoji
+    if (c as u32) > 0xffff {
+      match config.emoji {
+        EmojiMode::Keep => shaped.push(c),
+        EmojiMode::Fold => shaped.push_str(fold_emoji(c).unwrap_or(&config.emoji_replacement)),
+        EmojiMode::Strip => {}
+      }
+      continue;
+    }
+
+    shaped.push(c);
+  }
+
+  if config.collapse_whitespace {
+    shaped = collapse_whitespace(&shaped);
+  }
+
+  shaped
+}
+
+/// Runs of spaces become one space, runs of blank lines become one newline
+fn collapse_whitespace(text: &str) -> String {
+  text
+    .lines()
+    .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+    .filter(|line| !line.is_empty())
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+/// Biggest prefix of `text` that fits in `budget` bytes, preferring to break on whitespace
+/// as long as that doesnt waste more than half the budget
+fn split_point(text: &str, budget: usize) -> usize {
+  if text.len() <= budget {
+    return text.len();
+  }
+
+  let mut end = budget;
+  while !text.is_char_boundary(end) {
+    end -= 1;
+  }
+
+  match text[..end].rfind(char::is_whitespace) {
+    Some(space) if space > budget / 2 => space,
+    _ => end,
+  }
+}
+
+fn split(text: &str, budget: usize) -> Vec<String> {
+  let mut chunks = vec![];
+  let mut rest = text.trim();
+
+  while !rest.is_empty() {
+    // a character bigger than the whole budget still has to go somewhere
+    let first = rest.chars().next().map_or(1, char::len_utf8);
+    let end = split_point(rest, budget).max(first);
+    chunks.push(rest[..end].trim_end().to_string());
+    rest = rest[end..].trim_start();
+  }
+
+  chunks
+}
+
+/// Splits a message into payloads of at most `max_bytes`, with a " (1/3)" style marker on
+/// each when it takes more than one. The markers count against the budget too, and get left
+/// off when theres no room for them next to any text.
+pub fn fragment(text: &str, max_bytes: usize) -> Vec<String> {
+  if text.len() <= max_bytes {
+    return vec![text.to_string()];
+  }
+
+  // the marker gets longer with more fragments, so keep guessing until the count settles
+  let mut count = 2;
+  loop {
+    let marker_len = format!(" ({}/{})", count, count).len();
+    // the marker plus room for at least one character of any size
+    if max_bytes < marker_len + char::MAX.len_utf8() {
+      return split(text, max_bytes);
+    }
+    let chunks = split(text, max_bytes - marker_len);
+
+    if chunks.len() <= count {
+      let total = chunks.len();
+      return chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| format!("{} ({}/{})", chunk, index + 1, total))
+        .collect();
+    }
+    count = chunks.len();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn assert_fits(fragments: &[String], max_bytes: usize) {
+    for fragment in fragments {
+      assert!(
+        fragment.len() <= max_bytes,
+        "{:?} is {} bytes",
+        fragment,
+        fragment.len()
+      );
+    }
+  }
+
+  #[test]
+  fn emoji_survive_the_defaults() {
+    let config = ShapingConfig::default();
+    assert_eq!(shape("🚨 evacuate", &config), "🚨 evacuate");
+    assert_eq!(shape("🦊", &config), "🦊");
+  }
+
+  #[test]
+  fn folding_leaves_a_mark() {
+    let config = ShapingConfig {
+      emoji: EmojiMode::Fold,
+      ..Default::default()
+    };
+    assert_eq!(shape("🚨 evacuate 👍", &config), "? evacuate (y)");
+
+    let config = ShapingConfig {
+      emoji: EmojiMode::Strip,
+      ..Default::default()
+    };
+    assert_eq!(shape("🚨 evacuate", &config), "evacuate");
+  }
+
+  #[test]
+  fn short_messages_go_as_is() {
+    assert_eq!(fragment("hello mesh", 200), vec!["hello mesh"]);
+    assert_eq!(fragment("hello mesh", 10), vec!["hello mesh"]);
+  }
+
+  #[test]
+  fn long_messages_get_markers() {
+    let text = "the quick brown fox jumps over the lazy dog ".repeat(10);
+    let fragments = fragment(&text, 50);
+    assert_fits(&fragments, 50);
+
+    let total = fragments.len();
+    assert!(total > 1);
+    for (index, fragment) in fragments.iter().enumerate() {
+      assert!(
+        fragment.ends_with(&format!(" ({}/{})", index + 1, total)),
+        "{:?}",
+        fragment
+      );
+    }
+    // nothing lost but the whitespace at the breaks
+    let joined: Vec<&str> = fragments
+      .iter()
+      .map(|fragment| fragment.rsplit_once(" (").unwrap().0)
+      .collect();
+    assert_eq!(
+      joined.join(" ").split_whitespace().collect::<Vec<_>>(),
+      text.split_whitespace().collect::<Vec<_>>()
+    );
+  }
+
+  #[test]
+  fn breaks_on_whitespace() {
+    assert_eq!(split("aaaa bbbb cccc", 10), vec!["aaaa bbbb", "cccc"]);
+    // unless that would waste more than half the budget
+    assert_eq!(split("a bbbbbbbbbbbb", 10), vec!["a bbbbbbbb", "bbbb"]);
+  }
+
+  #[test]
+  fn multibyte_characters_stay_whole() {
+    let text = "ünïcödé ñ ".repeat(20) + &"日本語".repeat(20);
+    for max_bytes in [7, 8, 9, 10, 11, 50] {
+      let fragments = fragment(&text, max_bytes);
+      assert_fits(&fragments, max_bytes);
+    }
+    // a 3 byte character in a 2 byte budget, it goes out on its own rather than getting cut
+    assert_eq!(split("日本", 2), vec!["日", "本"]);
+  }
+
+  #[test]
+  fn no_markers_when_they_dont_fit() {
+    // " (2/2)" is already 6 bytes, which leaves no room for text
+    let fragments = fragment("abcdefghijkl", 6);
+    assert_eq!(fragments, vec!["abcdef", "ghijkl"]);
+
+    let fragments = fragment("ab cd ef gh ij", 4);
+    assert_fits(&fragments, 4);
+    assert_eq!(fragments.concat().replace(' ', ""), "abcdefghij");
+  }
+
+  #[test]
+  fn markers_grow_with_the_count() {
+    let text = "x".repeat(100);
+    let fragments = fragment(&text, 12);
+    assert_fits(&fragments, 12);
+    assert_eq!(fragments.len(), 25);
+    assert_eq!(fragments[24], "xxxx (25/25)");
+
+    // room for " (2/2)" but not for " (20/20)", which is what it would take
+    let fragments = fragment(&text, 11);
+    assert_fits(&fragments, 11);
+    assert_eq!(fragments.concat(), text);
+  }
+}
@@ -60,12 +60,11 @@ use tokio::{
   // io::{self, AsyncBufReadExt, BufReader},
 };
 use tracing::warn;
-use tracing::{error, info};
+use tracing::{debug, error, info};
 use url::Url;
 
 use crate::MyManager;
 use crate::Profile;
-use crate::logger::Logger;
 // #[derive(Parser)]
 // #[clap(about = "a basic signal CLI to try things out")]
 // struct Args {
@@ -263,10 +262,24 @@ pub fn attachments_tmp_dir() -> anyhow::Result<TempDir> {
 //   Args::parse()
 // }
 //
+#[cfg(not(test))]
 pub fn config_dir_path() -> String {
   "/home/dq1mango/.config/mesh-2-signal/".to_string()
 }
 
+/// every test thread gets its own scratch dir, so running the bridge in a test never touches
+/// the real pairings, outbox and so on
+#[cfg(test)]
+pub fn config_dir_path() -> String {
+  let dir = std::env::temp_dir().join(format!(
+    "mesh-2-signal-test-{}-{:?}",
+    std::process::id(),
+    std::thread::current().id()
+  ));
+  _ = std::fs::create_dir_all(&dir);
+  format!("{}/", dir.display())
+}
+
 pub fn default_db_path() -> String {
   // ProjectDirs::from("org", "whisperfish", "presage")
   //   .unwrap()
@@ -340,24 +353,23 @@ async fn send(
       if q.author_aci == None {
         q.author_aci = Some(manager.registration_data().service_ids.aci.to_string());
       }
-      Logger::log(format!("wow look at this quote: {:#?}", quote.clone()));
+      debug!(quote_id = ?q.id, "attaching quote");
       d.quote = quote;
     }
   }
 
   match recipient {
     Recipient::Contact(uuid) => {
-      info!(recipient =% uuid, "sending message to contact");
-      let result = manager
+      info!(recipient =% uuid, timestamp, "sending message to contact");
+      manager
         .send_message(ServiceId::Aci(uuid.into()), content_body, timestamp)
-        .await;
+        .await?;
     }
     Recipient::Group(master_key) => {
-      info!("sending message to group");
+      info!(group = %hex::encode(master_key), timestamp, "sending message to group");
       manager
         .send_message_to_group(&master_key, content_body, timestamp)
-        .await
-        .expect("failed to send message");
+        .await?;
     }
   }
   // --- dont want to be sleeping forever lol
@@ -370,7 +382,7 @@ async fn send(
   //   }
   // })
   // .await?;
-  Logger::log("done sending the message".to_string());
+  debug!(timestamp, "done sending the message");
 
   Ok(())
 }
@@ -580,7 +592,9 @@ async fn print_message<S: Store>(manager: &MyManager, notifications: bool, conte
       }
     };
 
-    println!("{prefix} / {body}");
+    // message contents stay out of the info logs, they end up on disk
+    info!(timestamp = ts, bytes = body.len(), "{prefix}");
+    debug!(timestamp = ts, "{prefix} / {body}");
 
     if notifications {
       if let Err(error) = Notification::new()
@@ -621,7 +635,7 @@ async fn receive(
     _ = output.send(Action::Receive(content));
   }
 
-  Logger::log("our reciever died rip".to_string());
+  warn!("signal message stream ended");
 
   Ok(())
 }
@@ -636,7 +650,7 @@ pub fn link_device(servers: SignalServers, device_name: String, output: mpsc::Un
 
     let (provisioning_link_tx, provisioning_link_rx) = oneshot::channel();
     let output1 = output.clone();
-    Logger::log(format!("about to send something, but gonna sleep a little first"));
+    debug!("waiting a bit before starting to link");
     sleep(Duration::from_secs(2)).await;
 
     let manager = future::join(
@@ -646,7 +660,7 @@ pub fn link_device(servers: SignalServers, device_name: String, output: mpsc::Un
         Manager::link_secondary_device(config_store, servers, device_name, provisioning_link_tx).await
       },
       async move {
-        Logger::log(format!("about to send something, feeling nervous"));
+        debug!("waiting for the provisioning url");
         match provisioning_link_rx.await {
           Ok(url) => {
             _ = output1.send(Action::Link(LinkingAction::Url(url)));
@@ -656,7 +670,7 @@ pub fn link_device(servers: SignalServers, device_name: String, output: mpsc::Un
       },
     )
     .await;
-    Logger::log(format!("i think it worked !!"));
+    info!(success = manager.0.is_ok(), "linking finished");
 
     match manager {
       (Ok(manager), _) => {
@@ -844,11 +858,11 @@ pub async fn run(
     //
     Cmd::AddDevice { url } => {
       manager.link_secondary(url).await?;
-      println!("Added new secondary device");
+      info!("added new secondary device");
     }
     Cmd::UnlinkDevice { device_id } => {
       manager.unlink_secondary(device_id).await?;
-      println!("Unlinked device with id: {}", device_id);
+      info!(device_id, "unlinked device");
     }
     Cmd::ListDevices => {
       let devices = manager.devices().await?;
@@ -862,9 +876,13 @@ pub async fn run(
           ""
         };
 
-        println!(
-          "- Device {} {}\n  Name: {}\n  Created: {}\n  Last seen: {}",
-          device.id, current_marker, device_name, device.created, device.last_seen,
+        info!(
+          device_id = device.id,
+          name = %device_name,
+          created = %device.created,
+          last_seen = %device.last_seen,
+          "device {}",
+          current_marker
         );
       }
     }
@@ -978,7 +996,7 @@ pub async fn run(
           target_sent_timestamp: Some(target_timestamp),
         };
         send(manager, recipient_from_thread(thread), timestamp, content, None).await?;
-        Logger::log("successfully sent the edit message");
+        info!(target_timestamp, "sent edit message");
       }
     }
     Cmd::ReactToThread {
@@ -1051,10 +1069,7 @@ pub async fn run(
         existing_msg.body = NullMessage::default().into();
         store.save_message(&thread, existing_msg).await?;
       } else {
-        Logger::log(format!(
-          "could not find message to delete in thread {}, {}",
-          thread, target_timestamp
-        ));
+        warn!(%thread, target_timestamp, "could not find message to delete");
       }
 
       send(
@@ -1066,7 +1081,7 @@ pub async fn run(
       )
       .await?;
 
-      Logger::log("successfully sent the delete message");
+      info!(target_timestamp, "sent delete message");
     }
     Cmd::RetrieveProfile { uuid, profile_key } => {}
     Cmd::ListGroups => {
@@ -1083,9 +1098,13 @@ pub async fn run(
             },
           )) => {
             let key = hex::encode(group_master_key);
-            println!(
-              "{key} {title}: {description:?} / revision {revision} / {} members",
-              members.len()
+            info!(
+              %key,
+              %title,
+              ?description,
+              revision,
+              members = members.len(),
+              "group"
             );
           }
           Err(error) => {
@@ -1102,24 +1121,25 @@ pub async fn run(
         ..
       } in manager.store().contacts().await?.flatten()
       {
-        println!("{uuid} / {phone_number:?} / {name}");
+        info!(%uuid, ?phone_number, %name, "contact");
       }
     }
     Cmd::ListStickerPacks => {
       for sticker_pack in manager.store().sticker_packs().await? {
         match sticker_pack {
           Ok(sticker_pack) => {
-            println!(
-              "title={} author={}",
-              sticker_pack.manifest.title, sticker_pack.manifest.author,
+            info!(
+              title = %sticker_pack.manifest.title,
+              author = %sticker_pack.manifest.author,
+              "sticker pack"
             );
             for sticker in sticker_pack.manifest.stickers {
-              println!(
-                "\tid={} emoji={} content_type={} bytes={}",
-                sticker.id,
-                sticker.emoji.unwrap_or_default(),
-                sticker.content_type.unwrap_or_default(),
-                sticker.bytes.unwrap_or_default().len(),
+              info!(
+                id = sticker.id,
+                emoji = %sticker.emoji.unwrap_or_default(),
+                content_type = %sticker.content_type.unwrap_or_default(),
+                bytes = sticker.bytes.unwrap_or_default().len(),
+                "sticker"
               )
             }
           }
@@ -1130,11 +1150,11 @@ pub async fn run(
       }
     }
     Cmd::Whoami => {
-      println!("{:?}", &manager.whoami().await?);
+      info!(whoami = ?manager.whoami().await?, "whoami");
     }
     Cmd::GetContact { ref uuid } => match manager.store().contact_by_id(uuid).await? {
-      Some(contact) => println!("{contact:#?}"),
-      None => eprintln!("Could not find contact for {uuid}"),
+      Some(contact) => info!(?contact, "contact"),
+      None => warn!(%uuid, "could not find contact"),
     },
     Cmd::FindContact {
       uuid,
@@ -1150,7 +1170,7 @@ pub async fn run(
         .filter(|c| c.phone_number == phone_number)
         .filter(|c| name.as_ref().is_none_or(|n| c.name.contains(n)))
       {
-        println!("{contact:#?}");
+        info!(?contact, "contact");
       }
     }
     Cmd::SyncContacts => {
@@ -1231,7 +1251,7 @@ pub async fn run(
         pni_kyber_pre_keys_count_last_resort: pni.kyber_pre_keys_count(LAST_RESORT).await.unwrap(),
       };
 
-      println!("{stats:#?}")
+      info!(?stats, "stats")
     }
   }
 
@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use crate::Model;
+use crate::Nodes;
+use crate::channels::bridge_setting;
+use crate::config::Config;
+
+/// "3h 12m" style durations, we dont need more precision than that
+pub fn format_age(age: Duration) -> String {
+  let secs = age.as_secs();
+  match secs {
+    0..60 => format!("{}s", secs),
+    60..3600 => format!("{}m", secs / 60),
+    3600..86400 => format!("{}h {}m", secs / 3600, (secs % 3600) / 60),
+    _ => format!("{}d {}h", secs / 86400, (secs % 86400) / 3600),
+  }
+}
+
+/// The name we show for a node, falling back to the `!xxxxxxxx` id everyone knows from the app
+pub fn node_display_name(nodes: &Nodes, node: u32) -> String {
+  match nodes.get(&node).and_then(|info| info.user.as_ref()) {
+    Some(user) => format!("{} (!{:08x})", user.long_name, node),
+    None => format!("!{:08x}", node),
+  }
+}
+
+/// "radio", or "radio <name>" once theres more than one to tell apart
+pub fn radio_label(config: &Config, radio: usize) -> String {
+  if config.radios.len() > 1 {
+    format!("radio {}", config.radios[radio].name)
+  } else {
+    "radio".to_string()
+  }
+}
+
+/// Everything someone on either side would want to know to figure out if the other side is
+/// actually reachable. Kept short enough to be somewhat reasonable over the mesh.
+pub fn status_report(model: &Model, config: &Config, queued: usize) -> String {
+  let mut lines = vec![
+    "Gateway status".to_string(),
+    format!("up {}", format_age(model.started_at.elapsed())),
+  ];
+
+  for (index, (state, radio_config)) in model.radios.iter().zip(&config.radios).enumerate() {
+    let label = radio_label(config, index);
+
+    let mut radio = if state.connected {
+      format!("{}: connected on {}", label, radio_config.port)
+    } else {
+      format!("{}: OFFLINE ({})", label, radio_config.port)
+    };
+    if let Some(version) = state.info.firmware_version() {
+      radio.push_str(&format!(", fw {}", version));
+    }
+    if let Some(node) = state.my_node_num {
+      radio.push_str(&format!(", node !{:08x}", node));
+    }
+    lines.push(radio);
+
+    let bridged = state.channels.bridged(config, radio_config);
+    let channel = match bridged.and_then(|index| Some((index, state.channels.name(index)?))) {
+      Some((index, name)) => format!("channel: {} (#{})", name, index),
+      None => match bridge_setting(config, radio_config) {
+        (_, Some(name)) => format!("channel: {} (not on the radio)", name),
+        (index, None) => format!("channel: #{} (unknown)", index),
+      },
+    };
+    lines.push(channel);
+  }
+
+  lines.push(if model.signal_synced {
+    "signal: linked, synced".to_string()
+  } else {
+    "signal: linked, still syncing".to_string()
+  });
+
+  lines.push(format!("queued: {}, unacked: {}", queued, model.mesh_to_signal.len()));
+
+  lines.push(match &model.last_packet {
+    Some((radio, node, when)) => format!(
+      "last heard: {} {} ago",
+      node_display_name(&model.radios[*radio].nodes, *node),
+      format_age(when.elapsed())
+    ),
+    None => "last heard: nothing yet".to_string(),
+  });
+
+  lines.join("\n")
+}
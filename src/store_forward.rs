@@ -0,0 +1,442 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+
+use chrono::Utc;
+use meshtastic::packet::PacketDestination;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::SignalMessage;
+use crate::config::StoreForwardConfig;
+use crate::metrics::METRICS;
+use crate::signal::config_dir_path;
+use crate::update::Action;
+
+/// A signal -> mesh message that couldnt go out because the radio was gone
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct QueuedMessage {
+  /// which radio its for, by name so a reordered config doesnt send it out the wrong one.
+  /// outboxes from before there could be more than one radio dont have it
+  #[serde(default)]
+  pub radio: Option<String>,
+  pub body: String,
+  pub channel: u32,
+  /// `None` means broadcast
+  pub destination: Option<u32>,
+  /// unix seconds
+  pub queued_at: i64,
+  pub signal_message: Option<SignalMessage>,
+  /// one piece of a message that got split up already, it has its " (i/n)" marker and goes
+  /// out exactly as it is
+  #[serde(default)]
+  pub fragment: bool,
+}
+
+// toml wont let us have an array at the top level
+#[derive(Default, Deserialize, Serialize)]
+struct OutboxFile {
+  #[serde(default)]
+  messages: Vec<QueuedMessage>,
+}
+
+/// Durable queue of messages waiting for the radio to come back. Every change is written
+/// straight to disk so a crash (or a restart) doesnt eat anything.
+pub struct Outbox {
+  path: String,
+  messages: VecDeque<QueuedMessage>,
+  /// flushed but not sent yet, still on disk in case we dont get that far
+  in_flight: Vec<(u64, QueuedMessage)>,
+  next_id: u64,
+  config: StoreForwardConfig,
+}
+
+fn outbox_path() -> String {
+  let mut dir = config_dir_path();
+  dir.push_str("outbox.toml");
+  dir
+}
+
+/// Reads a toml file the way `save_toml` left it. `Ok(None)` just means theres no file yet
+pub fn load_toml<T: DeserializeOwned>(path: &str) -> Result<Option<T>, String> {
+  match fs::read_to_string(path) {
+    Ok(contents) => toml::from_str(&contents).map(Some).map_err(|err| err.to_string()),
+    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+    Err(err) => Err(err.to_string()),
+  }
+}
+
+/// Writes a tmp file and renames it over `path`, so a crash mid write leaves the old file
+/// instead of half of the new one
+pub fn save_toml<T: Serialize>(path: &str, value: &T) -> Result<(), String> {
+  let contents = toml::to_string(value).map_err(|err| err.to_string())?;
+  let tmp_path = format!("{}.tmp", path);
+  fs::write(&tmp_path, contents)
+    .and_then(|_| fs::rename(&tmp_path, path))
+    .map_err(|err| err.to_string())
+}
+
+impl Outbox {
+  pub fn load(config: &StoreForwardConfig) -> Self {
+    let path = outbox_path();
+
+    let messages = match load_toml::<OutboxFile>(&path) {
+      Ok(Some(file)) => file.messages.into(),
+      // no file just means nothing was queued
+      Ok(None) => VecDeque::new(),
+      Err(err) => {
+        error!(%err, %path, "outbox file is unreadable, starting fresh");
+        VecDeque::new()
+      }
+    };
+
+    if !messages.is_empty() {
+      info!(queued = messages.len(), "loaded queued messages from the outbox");
+    }
+    METRICS.set_outbox_depth(messages.len());
+
+    Self {
+      path,
+      messages,
+      in_flight: vec![],
+      next_id: 0,
+      config: config.clone(),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.messages.len() + self.in_flight.len()
+  }
+
+  pub fn push(
+    &mut self,
+    radio: &str,
+    body: String,
+    channel: u32,
+    destination: PacketDestination,
+    signal_message: Option<SignalMessage>,
+    fragment: bool,
+  ) {
+    let destination = match destination {
+      PacketDestination::Node(node) => Some(node.id()),
+      _ => None,
+    };
+
+    self.messages.push_back(QueuedMessage {
+      radio: Some(radio.to_string()),
+      body,
+      channel,
+      destination,
+      queued_at: Utc::now().timestamp(),
+      signal_message,
+      fragment,
+    });
+
+    while self.messages.len() > self.config.max_messages {
+      if let Some(dropped) = self.messages.pop_front() {
+        warn!(
+          queued_at = dropped.queued_at,
+          bytes = dropped.body.len(),
+          "outbox full, dropping oldest message"
+        );
+      }
+    }
+
+    info!(
+      radio,
+      channel,
+      ?destination,
+      queued = self.messages.len(),
+      "queued message for the mesh"
+    );
+    self.persist();
+  }
+
+  /// Turns a radios part of the queue into `SendToMesh` actions (oldest first), dropping
+  /// anything past the age limit and marking the rest as late. Whichever radio comes up first
+  /// gets the messages that dont say. They stay on disk until `sent` says they made it.
+  pub fn flush(&mut self, radio: usize, name: &str) -> Vec<Action> {
+    let now = Utc::now().timestamp();
+    let max_age = (self.config.max_age_minutes * 60) as i64;
+
+    let (flushing, waiting): (VecDeque<_>, VecDeque<_>) = self
+      .messages
+      .drain(..)
+      .partition(|queued| queued.radio.as_deref().is_none_or(|queued| queued == name));
+    self.messages = waiting;
+
+    let mut actions = Vec::with_capacity(flushing.len());
+    for queued in flushing {
+      let age = now - queued.queued_at;
+      if age > max_age {
+        warn!(
+          age_secs = age,
+          bytes = queued.body.len(),
+          "dropping stale queued message"
+        );
+        continue;
+      }
+
+      let minutes = age / 60;
+      // a prefix on a fragment would push it past the size it was cut to
+      let body = if minutes > 0 && !queued.fragment {
+        format!("[delayed {}m] {}", minutes, queued.body)
+      } else {
+        queued.body
+      };
+
+      let destination = match queued.destination {
+        Some(node) => PacketDestination::Node(node.into()),
+        None => PacketDestination::Broadcast,
+      };
+
+      let id = self.next_id;
+      self.next_id += 1;
+      actions.push(Action::SendToMesh {
+        radio,
+        body,
+        channel: queued.channel.into(),
+        destination,
+        signal_message: queued.signal_message.clone(),
+        outbox_id: Some(id),
+      });
+      self.in_flight.push((id, queued));
+    }
+
+    self.persist();
+    actions
+  }
+
+  /// A flushed message made it to the radio, it can go for good
+  pub fn sent(&mut self, id: u64) {
+    self.in_flight.retain(|(in_flight, _)| *in_flight != id);
+    self.persist();
+  }
+
+  /// A flushed message couldnt go out after all. It goes back in the queue as it was, so it
+  /// keeps its place and its original delay
+  pub fn requeue(&mut self, id: u64) {
+    let Some(index) = self.in_flight.iter().position(|(in_flight, _)| *in_flight == id) else {
+      return;
+    };
+    let (_, queued) = self.in_flight.remove(index);
+    info!(radio = ?queued.radio, queued_at = queued.queued_at, "requeued message for the mesh");
+    let index = self.queue_position(&queued);
+    self.messages.insert(index, queued);
+    self.persist();
+  }
+
+  /// Only the first few fragments of a flushed message went out. The rest go back in its place
+  /// as ready made fragments, so the ones that made it dont get sent twice
+  pub fn requeue_rest(&mut self, id: u64, rest: Vec<String>) {
+    let Some(index) = self.in_flight.iter().position(|(in_flight, _)| *in_flight == id) else {
+      return;
+    };
+    let (_, queued) = self.in_flight.remove(index);
+    info!(
+      radio = ?queued.radio,
+      queued_at = queued.queued_at,
+      fragments = rest.len(),
+      "requeued the rest of a message for the mesh"
+    );
+    let index = self.queue_position(&queued);
+    let last = rest.len().saturating_sub(1);
+    for (offset, body) in rest.into_iter().enumerate() {
+      self.messages.insert(
+        index + offset,
+        QueuedMessage {
+          body,
+          // the reaction goes on once the last fragment makes it
+          signal_message: if offset == last {
+            queued.signal_message.clone()
+          } else {
+            None
+          },
+          fragment: true,
+          ..queued.clone()
+        },
+      );
+    }
+    self.persist();
+  }
+
+  /// Where something queued at the same time as `queued` goes back in, behind anything older
+  fn queue_position(&self, queued: &QueuedMessage) -> usize {
+    self
+      .messages
+      .iter()
+      .position(|waiting| waiting.queued_at > queued.queued_at)
+      .unwrap_or(self.messages.len())
+  }
+
+  fn persist(&self) {
+    METRICS.set_outbox_depth(self.len());
+
+    // anything in flight is still owed to the mesh if we crash now
+    let file = OutboxFile {
+      messages: self
+        .in_flight
+        .iter()
+        .map(|(_, queued)| queued)
+        .chain(&self.messages)
+        .cloned()
+        .collect(),
+    };
+
+    if let Err(err) = save_toml(&self.path, &file) {
+      error!(%err, "failed to persist outbox");
+    }
+  }
+}
+
+// same deal as the outbox, toml needs a table at the top
+#[derive(Default, Deserialize, Serialize)]
+struct PendingAcksFile {
+  #[serde(default)]
+  pending: Vec<PendingAck>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct PendingAck {
+  packet_id: u32,
+  message: SignalMessage,
+}
+
+fn pending_acks_path() -> String {
+  let mut dir = config_dir_path();
+  dir.push_str("pending_acks.toml");
+  dir
+}
+
+/// Saves the mesh packet id -> signal message mapping so acks that land after a restart
+/// still get their reaction
+pub fn save_pending_acks(pending: &HashMap<u32, SignalMessage>) {
+  let file = PendingAcksFile {
+    pending: pending
+      .iter()
+      .map(|(packet_id, message)| PendingAck {
+        packet_id: *packet_id,
+        message: message.clone(),
+      })
+      .collect(),
+  };
+
+  match save_toml(&pending_acks_path(), &file) {
+    Ok(_) => info!(pending = pending.len(), "saved unacked messages"),
+    Err(err) => error!(%err, "failed to save unacked messages"),
+  }
+}
+
+/// Picks the mapping back up and removes the file, so a crash later doesnt bring back acks
+/// that already got their reaction. Shutdown saves whatever is still pending
+pub fn load_pending_acks() -> HashMap<u32, SignalMessage> {
+  let path = pending_acks_path();
+  match load_toml::<PendingAcksFile>(&path) {
+    Ok(Some(file)) => {
+      if let Err(err) = fs::remove_file(&path) {
+        warn!(%err, %path, "failed to remove the pending acks file");
+      }
+      file
+        .pending
+        .into_iter()
+        .map(|pending| (pending.packet_id, pending.message))
+        .collect()
+    }
+    Ok(None) => HashMap::new(),
+    Err(err) => {
+      error!(%err, "pending acks file is unreadable, ignoring it");
+      HashMap::new()
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Uuid;
+
+  fn bodies(actions: &[Action]) -> Vec<(&str, bool)> {
+    actions
+      .iter()
+      .map(|action| match action {
+        Action::SendToMesh {
+          body, signal_message, ..
+        } => (body.as_str(), signal_message.is_some()),
+        other => panic!("not a mesh send: {:?}", other),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn half_sent_messages_only_requeue_the_rest() {
+    let mut outbox = Outbox::load(&StoreForwardConfig::default());
+    outbox.messages.clear();
+    let message = SignalMessage {
+      body: "a long one".to_string(),
+      sender: Uuid::nil(),
+      timestamp: 1,
+      contact: None,
+      direct: false,
+    };
+    outbox.push(
+      "radio",
+      "a long one".to_string(),
+      1,
+      PacketDestination::Broadcast,
+      Some(message),
+      false,
+    );
+    // its been waiting a while, so it gets marked as late
+    outbox.messages[0].queued_at -= 5 * 60;
+
+    let flushed = outbox.flush(0, "radio");
+    assert_eq!(bodies(&flushed), vec![("[delayed 5m] a long one", true)]);
+    let Action::SendToMesh {
+      outbox_id: Some(id), ..
+    } = &flushed[0]
+    else {
+      panic!("flushed without an outbox id");
+    };
+
+    // fragment 1/3 made it, 2/3 didnt
+    outbox.requeue_rest(*id, vec!["b (2/3)".to_string(), "c (3/3)".to_string()]);
+    assert_eq!(outbox.len(), 2);
+
+    // no second round of markers or delay prefixes, and the reaction waits for the last one
+    let flushed = outbox.flush(0, "radio");
+    assert_eq!(bodies(&flushed), vec![("b (2/3)", false), ("c (3/3)", true)]);
+  }
+  #[test]
+  fn saves_leave_no_tmp_file_behind() {
+    let mut path = config_dir_path();
+    path.push_str("save_toml_test.toml");
+    assert_eq!(load_toml::<OutboxFile>(&path).map(|file| file.is_none()), Ok(true));
+
+    let file = OutboxFile { messages: vec![] };
+    save_toml(&path, &file).unwrap();
+    let loaded = load_toml::<OutboxFile>(&path).unwrap().expect("nothing was saved");
+    assert!(loaded.messages.is_empty());
+    assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+
+    fs::write(&path, "messages = 3").unwrap();
+    assert!(load_toml::<OutboxFile>(&path).is_err());
+  }
+
+  #[test]
+  fn pending_acks_are_only_loaded_once() {
+    let message = SignalMessage {
+      body: "hi".to_string(),
+      sender: Uuid::nil(),
+      timestamp: 1,
+      contact: None,
+      direct: false,
+    };
+    save_pending_acks(&HashMap::from([(7, message)]));
+
+    let loaded = load_pending_acks();
+    assert_eq!(loaded.keys().collect::<Vec<_>>(), vec![&7]);
+    assert!(!std::path::Path::new(&pending_acks_path()).exists());
+    assert!(load_pending_acks().is_empty());
+  }
+}
@@ -0,0 +1,180 @@
+use presage::proto::{
+  BodyRange,
+  body_range::{AssociatedValue, Style},
+};
+
+/// Everything a template can refer to. Whatever doesnt apply to a direction (ie. hops for
+/// stuff coming from signal) just renders empty.
+#[derive(Debug, Default)]
+pub struct TemplateVars<'a> {
+  pub name: &'a str,
+  pub short: &'a str,
+  pub id: &'a str,
+  pub channel: &'a str,
+  pub radio: &'a str,
+  pub hops: Option<u32>,
+  pub snr: Option<f32>,
+  pub time: &'a str,
+  pub body: &'a str,
+}
+
+#[derive(Debug)]
+pub struct Rendered {
+  pub text: String,
+  /// where the first `{name}` or `{short}` ended up, in utf-16 units since thats what signal
+  /// counts body ranges in
+  name_span: Option<(u32, u32)>,
+}
+
+impl Rendered {
+  /// Bold over the senders name, if the template has it at all
+  pub fn name_ranges(&self) -> Vec<BodyRange> {
+    match self.name_span {
+      Some((start, length)) if length > 0 => vec![BodyRange {
+        start: Some(start),
+        length: Some(length),
+        associated_value: Some(AssociatedValue::Style(Style::Bold.into())),
+      }],
+      _ => vec![],
+    }
+  }
+}
+
+fn utf16_len(text: &str) -> u32 {
+  text.encode_utf16().count() as u32
+}
+
+/// Fills in `{placeholders}`, anything we dont recognize gets left as is so a typo in the
+/// config is obvious in the output instead of silently vanishing
+pub fn render(template: &str, vars: &TemplateVars) -> Rendered {
+  let mut text = String::with_capacity(template.len() + vars.body.len());
+  let mut name_span = None;
+  let mut rest = template;
+
+  while let Some(open) = rest.find('{') {
+    text.push_str(&rest[..open]);
+    rest = &rest[open..];
+
+    let Some(close) = rest.find('}') else {
+      break;
+    };
+    let placeholder = &rest[1..close];
+
+    let value = match placeholder {
+      "name" => vars.name.to_string(),
+      "short" => vars.short.to_string(),
+      "id" => vars.id.to_string(),
+      "channel" => vars.channel.to_string(),
+      "radio" => vars.radio.to_string(),
+      "hops" => vars.hops.map(|hops| hops.to_string()).unwrap_or_default(),
+      "snr" => vars.snr.map(|snr| format!("{:.1}", snr)).unwrap_or_default(),
+      "time" => vars.time.to_string(),
+      "body" => vars.body.to_string(),
+      _ => {
+        text.push('{');
+        rest = &rest[1..];
+        continue;
+      }
+    };
+
+    if name_span.is_none() && (placeholder == "name" || placeholder == "short") {
+      name_span = Some((utf16_len(&text), utf16_len(&value)));
+    }
+    text.push_str(&value);
+    rest = &rest[close + 1..];
+  }
+  text.push_str(rest);
+
+  Rendered { text, name_span }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn vars() -> TemplateVars<'static> {
+    TemplateVars {
+      name: "Alice",
+      short: "ALIC",
+      id: "0000a11c",
+      channel: "gateway",
+      radio: "radio",
+      hops: Some(2),
+      snr: Some(6.5),
+      time: "12:34",
+      body: "hi",
+    }
+  }
+
+  #[test]
+  fn fills_in_everything() {
+    let rendered = render(
+      "[{channel}/{radio} {time}] {name} ({short} {id}, {hops} hops, {snr}dB): {body}",
+      &vars(),
+    );
+    assert_eq!(
+      rendered.text,
+      "[gateway/radio 12:34] Alice (ALIC 0000a11c, 2 hops, 6.5dB): hi"
+    );
+  }
+
+  #[test]
+  fn missing_values_render_empty() {
+    let vars = TemplateVars {
+      hops: None,
+      snr: None,
+      ..vars()
+    };
+    assert_eq!(render("{name} [{hops}|{snr}]", &vars).text, "Alice [|]");
+  }
+
+  #[test]
+  fn unknown_placeholders_stay() {
+    let rendered = render("{nope} <{short}> {body}", &vars());
+    assert_eq!(rendered.text, "{nope} <ALIC> hi");
+    assert_eq!(rendered.name_span, Some((8, 4)));
+
+    assert_eq!(render("{{name}}", &vars()).text, "{Alice}");
+    assert_eq!(render("{name", &vars()).text, "{name");
+    assert_eq!(render("{body} {", &vars()).text, "hi {");
+    assert_eq!(render("", &vars()).text, "");
+  }
+
+  #[test]
+  fn name_range_counts_utf16() {
+    let vars = TemplateVars {
+      name: "Zo\u{eb} 🦊",
+      body: "héllo 👋",
+      ..vars()
+    };
+    // the satellite is two utf-16 units and the variation selector one more
+    let rendered = render("🛰\u{fe0f} {name}: {body}", &vars);
+    assert_eq!(rendered.text, "🛰\u{fe0f} Zo\u{eb} 🦊: héllo 👋");
+    assert_eq!(rendered.name_span, Some((4, 6)));
+
+    let ranges = rendered.name_ranges();
+    assert_eq!(ranges.len(), 1);
+    assert_eq!(ranges[0].start, Some(4));
+    assert_eq!(ranges[0].length, Some(6));
+    assert_eq!(
+      ranges[0].associated_value,
+      Some(AssociatedValue::Style(Style::Bold.into()))
+    );
+  }
+
+  #[test]
+  fn only_the_first_name_is_bold() {
+    let rendered = render("{short}: {body} -{name}", &vars());
+    assert_eq!(rendered.name_span, Some((0, 4)));
+  }
+
+  #[test]
+  fn no_name_no_range() {
+    assert!(render("{body}", &vars()).name_ranges().is_empty());
+
+    let vars = TemplateVars { name: "", ..vars() };
+    let rendered = render("{name}: {body}", &vars);
+    assert_eq!(rendered.text, ": hi");
+    assert!(rendered.name_ranges().is_empty());
+  }
+}
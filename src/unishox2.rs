@@ -0,0 +1,609 @@
+//! Unishox2, the short string compression meshtastic uses for `TEXT_MESSAGE_COMPRESSED_APP`,
+//! with the default preset. The decoder handles everything short chat messages use (the three
+//! character sets, case, unicode deltas, repeats, back references and the frequent sequences),
+//! but not the number/hex templates, those come back as an error. The encoder sticks to the
+//! plain subset, which every unishox2 decoder understands.
+
+use anyhow::{anyhow, bail};
+
+const ALPHA: usize = 0;
+const SYM: usize = 1;
+const NUM: usize = 2;
+const DICT: usize = 3;
+const DELTA: usize = 4;
+
+const SETS: [[u8; 28]; 3] = [
+  [
+    0, b' ', b'e', b't', b'a', b'o', b'i', b'n', b's', b'r', b'l', b'c', b'd', b'h', b'u', b'p', b'm', b'b', b'g',
+    b'w', b'f', b'y', b'v', b'k', b'q', b'j', b'x', b'z',
+  ],
+  [
+    b'"', b'{', b'}', b'_', b'<', b'>', b':', b'\n', 0, b'[', b']', b'\\', b';', b'\'', b'\t', b'@', b'*', b'&', b'?',
+    b'!', b'^', b'|', b'\r', b'~', b'`', 0, 0, 0,
+  ],
+  [
+    0, b',', b'.', b'0', b'1', b'9', b'2', b'5', b'-', b'/', b'3', b'4', b'6', b'7', b'8', b'(', b')', b' ', b'=',
+    b'+', b'$', b'%', b'#', 0, 0, 0, 0, 0,
+  ],
+];
+
+const VCODES: [u8; 28] = [
+  0x00, 0x40, 0x60, 0x80, 0x90, 0xa0, 0xb0, 0xc0, 0xd0, 0xd8, 0xe0, 0xe4, 0xe8, 0xec, 0xee, 0xf0, 0xf2, 0xf4, 0xf6,
+  0xf7, 0xf8, 0xf9, 0xfa, 0xfb, 0xfc, 0xfd, 0xfe, 0xff,
+];
+const VCODE_LENS: [u8; 28] = [
+  2, 3, 3, 4, 4, 4, 4, 4, 5, 5, 6, 6, 6, 7, 7, 7, 7, 7, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8,
+];
+
+const HCODES: [u8; 5] = [0x00, 0x40, 0x80, 0xc0, 0xe0];
+const HCODE_LENS: [u8; 5] = [2, 2, 2, 3, 3];
+
+const FREQ_SEQ: [&str; 6] = ["\": \"", "\": ", "</", "=\"", "\":\"", "://"];
+
+const COUNT_BIT_LENS: [u32; 5] = [2, 4, 7, 11, 16];
+const COUNT_ADDER: [u32; 5] = [0, 4, 20, 148, 2196];
+const UNI_BIT_LENS: [u32; 5] = [6, 12, 14, 16, 21];
+const UNI_ADDER: [u32; 5] = [0, 64, 4160, 20544, 86080];
+
+// the spot in the number set thats used as the end marker
+const TERM: usize = 27;
+const REPEAT: usize = 26;
+// back references are at least this long
+const NICE_LEN: usize = 5;
+/// way more than fits in a packet even compressed, repeats and back references can ask for
+/// tens of kilobytes from a few bits so anything past this is garbage or someone being funny
+const MAX_OUTPUT: usize = 4096;
+
+struct BitReader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+}
+
+impl BitReader<'_> {
+  fn len(&self) -> usize {
+    self.bytes.len() * 8
+  }
+
+  fn bit(&self, pos: usize) -> u8 {
+    match self.bytes.get(pos / 8) {
+      Some(byte) => (byte >> (7 - pos % 8)) & 1,
+      None => 0,
+    }
+  }
+
+  /// The next 8 bits without moving, zero padded past the end
+  fn peek8(&self) -> u8 {
+    (0..8).fold(0, |acc, offset| (acc << 1) | self.bit(self.pos + offset))
+  }
+
+  fn read(&mut self, bits: u32) -> Option<u32> {
+    if self.pos + bits as usize > self.len() {
+      return None;
+    }
+    let value = (0..bits as usize).fold(0, |acc, offset| (acc << 1) | self.bit(self.pos + offset) as u32);
+    self.pos += bits as usize;
+    Some(value)
+  }
+
+  fn read_code(&mut self, codes: &[u8], lens: &[u8]) -> Option<usize> {
+    let peeked = self.peek8();
+    for (index, (&code, &len)) in codes.iter().zip(lens).enumerate() {
+      let mask = (0xff00u16 >> len) as u8;
+      if len > 0 && peeked & mask == code {
+        if self.pos + len as usize > self.len() {
+          return None;
+        }
+        self.pos += len as usize;
+        return Some(index);
+      }
+    }
+    None
+  }
+
+  fn vcode(&mut self) -> Option<usize> {
+    self.read_code(&VCODES, &VCODE_LENS)
+  }
+
+  fn hcode(&mut self) -> Option<usize> {
+    self.read_code(&HCODES, &HCODE_LENS)
+  }
+
+  /// "0", "10", "110"... up to `limit` ones in a row
+  fn step(&mut self, limit: usize) -> Option<usize> {
+    let mut index = 0;
+    while index < limit {
+      if self.read(1)? == 0 {
+        break;
+      }
+      index += 1;
+    }
+    Some(index)
+  }
+
+  fn count(&mut self) -> Option<usize> {
+    let index = self.step(4)?;
+    Some((self.read(COUNT_BIT_LENS[index])? + COUNT_ADDER[index]) as usize)
+  }
+}
+
+enum Unicode {
+  Delta(i64),
+  Special(usize),
+}
+
+fn read_unicode(reader: &mut BitReader) -> Option<Unicode> {
+  let index = reader.step(5)?;
+  if index == 5 {
+    return Some(Unicode::Special(reader.step(4)?));
+  }
+  let negative = reader.read(1)? == 1;
+  let value = (reader.read(UNI_BIT_LENS[index])? + UNI_ADDER[index]) as i64;
+  Some(Unicode::Delta(if negative { -value } else { value }))
+}
+
+fn push_code_point(out: &mut Vec<u8>, code: i64) -> anyhow::Result<()> {
+  let c = u32::try_from(code)
+    .ok()
+    .and_then(char::from_u32)
+    .ok_or_else(|| anyhow!("bad code point in compressed text: {}", code))?;
+  let mut buf = [0; 4];
+  out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+  Ok(())
+}
+
+fn check_room(out: &[u8], more: usize) -> anyhow::Result<()> {
+  if out.len() + more > MAX_OUTPUT {
+    bail!("compressed text would decompress to more than {} bytes", MAX_OUTPUT);
+  }
+  Ok(())
+}
+
+/// What a zero entry in one of the sets means, `Ok(false)` being the end marker
+fn special(out: &mut Vec<u8>, reader: &mut BitReader, set: usize, index: usize) -> anyhow::Result<bool> {
+  match (set, index) {
+    (SYM, 8) => out.extend_from_slice(b"\r\n"),
+    (SYM, 25..=27) => out.extend_from_slice(FREQ_SEQ[index - 25].as_bytes()),
+    (NUM, 23..=25) => out.extend_from_slice(FREQ_SEQ[index - 20].as_bytes()),
+    (NUM, REPEAT) => {
+      let count = reader.count().ok_or_else(|| anyhow!("truncated repeat"))? + 4;
+      let Some(&last) = out.last() else {
+        bail!("repeat with nothing to repeat");
+      };
+      check_room(out, count)?;
+      out.extend(std::iter::repeat_n(last, count));
+    }
+    (NUM, TERM) => return Ok(false),
+    _ => bail!("unsupported code {} in set {}", index, set),
+  }
+  Ok(true)
+}
+
+pub fn decompress(bytes: &[u8]) -> anyhow::Result<String> {
+  let mut reader = BitReader { bytes, pos: 0 };
+  let mut out = Vec::with_capacity(bytes.len() * 2);
+
+  // the magic bit
+  reader.pos = 1;
+
+  let mut state = ALPHA;
+  let mut all_upper = false;
+  let mut prev_uni: i64 = 0;
+
+  while reader.pos < reader.len() {
+    // nothing else adds more than a code point at a time
+    check_room(&out, 4)?;
+
+    if state == DELTA {
+      let Some(unicode) = read_unicode(&mut reader) else {
+        break;
+      };
+      match unicode {
+        Unicode::Delta(delta) => {
+          prev_uni += delta;
+          push_code_point(&mut out, prev_uni)?;
+        }
+        Unicode::Special(0) => out.push(b' '),
+        Unicode::Special(2) => out.push(b','),
+        Unicode::Special(3) => out.push(b'.'),
+        Unicode::Special(4) => out.push(b'\n'),
+        Unicode::Special(1) => {
+          let Some(set) = reader.hcode() else {
+            break;
+          };
+          match set {
+            ALPHA => state = ALPHA,
+            SYM | NUM => {
+              let Some(index) = reader.vcode() else {
+                break;
+              };
+              let c = SETS[set][index];
+              if c == 0 {
+                if !special(&mut out, &mut reader, set, index)? {
+                  break;
+                }
+              } else {
+                if c.is_ascii_digit() {
+                  state = NUM;
+                }
+                out.push(c);
+              }
+            }
+            _ => bail!("unsupported switch out of unicode mode"),
+          }
+        }
+        Unicode::Special(_) => bail!("unknown special code in unicode mode"),
+      }
+      continue;
+    }
+
+    let mut set = state;
+    let mut is_upper = all_upper;
+    let Some(mut index) = reader.vcode() else {
+      break;
+    };
+
+    if index == 0 {
+      let Some(switch) = reader.hcode() else {
+        break;
+      };
+      match switch {
+        ALPHA if state == ALPHA => {
+          if all_upper {
+            all_upper = false;
+            continue;
+          }
+          let Some(next) = reader.vcode() else {
+            break;
+          };
+          index = next;
+          if index == 0 {
+            let Some(next) = reader.vcode() else {
+              break;
+            };
+            if next == 0 {
+              all_upper = true;
+              continue;
+            }
+            index = next;
+          }
+          is_upper = true;
+        }
+        ALPHA => {
+          state = ALPHA;
+          continue;
+        }
+        DICT => {
+          let length = reader.count().ok_or_else(|| anyhow!("truncated back reference"))? + NICE_LEN;
+          let distance = reader.count().ok_or_else(|| anyhow!("truncated back reference"))? + NICE_LEN - 1;
+          if distance > out.len() {
+            bail!("back reference past the start of the message");
+          }
+          check_room(&out, length)?;
+          let start = out.len() - distance;
+          for offset in 0..length {
+            out.push(out[start + offset]);
+          }
+          continue;
+        }
+        DELTA => {
+          match read_unicode(&mut reader) {
+            Some(Unicode::Delta(delta)) => {
+              prev_uni += delta;
+              push_code_point(&mut out, prev_uni)?;
+            }
+            Some(Unicode::Special(0)) => out.push(b' '),
+            Some(Unicode::Special(2)) => out.push(b','),
+            Some(Unicode::Special(3)) => out.push(b'.'),
+            Some(Unicode::Special(4)) => out.push(b'\n'),
+            Some(Unicode::Special(_)) => bail!("unsupported special code"),
+            None => break,
+          }
+          continue;
+        }
+        NUM if state == NUM => bail!("number templates arent supported"),
+        _ => {
+          set = switch;
+          let Some(next) = reader.vcode() else {
+            break;
+          };
+          index = next;
+        }
+      }
+    }
+
+    // uppercase space is how unicode mode gets turned on
+    if is_upper && set == ALPHA && index == 1 {
+      state = DELTA;
+      continue;
+    }
+
+    let c = SETS[set][index];
+    if c == 0 {
+      if !special(&mut out, &mut reader, set, index)? {
+        break;
+      }
+      continue;
+    }
+
+    if c.is_ascii_lowercase() {
+      out.push(if is_upper { c.to_ascii_uppercase() } else { c });
+    } else {
+      if c.is_ascii_digit() {
+        state = NUM;
+      }
+      out.push(c);
+    }
+  }
+
+  Ok(String::from_utf8(out)?)
+}
+
+#[derive(Default)]
+struct BitWriter {
+  bytes: Vec<u8>,
+  bits: usize,
+}
+
+impl BitWriter {
+  /// Appends the top `len` bits of `code`
+  fn push(&mut self, code: u8, len: u8) {
+    for offset in 0..len {
+      self.push_bit((code >> (7 - offset)) & 1);
+    }
+  }
+
+  fn push_bit(&mut self, bit: u8) {
+    if self.bits.is_multiple_of(8) {
+      self.bytes.push(0);
+    }
+    if bit == 1 {
+      *self.bytes.last_mut().unwrap() |= 1 << (7 - self.bits % 8);
+    }
+    self.bits += 1;
+  }
+
+  fn push_value(&mut self, value: u32, len: u32) {
+    for offset in (0..len).rev() {
+      self.push_bit(((value >> offset) & 1) as u8);
+    }
+  }
+
+  fn step(&mut self, index: usize, limit: usize) {
+    for _ in 0..index {
+      self.push_bit(1);
+    }
+    if index < limit {
+      self.push_bit(0);
+    }
+  }
+
+  fn vcode(&mut self, index: usize) {
+    self.push(VCODES[index], VCODE_LENS[index]);
+  }
+
+  fn hcode(&mut self, set: usize) {
+    self.push(HCODES[set], HCODE_LENS[set]);
+  }
+
+  /// Gets out of whatever mode were in so the next thing can be a set code
+  fn switch(&mut self, state: usize) {
+    if state == DELTA {
+      self.step(5, 5);
+      self.step(1, 4);
+    } else {
+      self.vcode(0);
+    }
+  }
+}
+
+fn position(set: usize, c: u8) -> Option<usize> {
+  SETS[set].iter().position(|&entry| entry != 0 && entry == c)
+}
+
+fn push_unicode(writer: &mut BitWriter, code: u32, prev: u32) {
+  let diff = (code as i64 - prev as i64).unsigned_abs() as u32;
+  for index in 0..5 {
+    if diff < UNI_ADDER[index] + (1 << UNI_BIT_LENS[index]) {
+      writer.step(index, 5);
+      writer.push_bit((prev > code) as u8);
+      writer.push_value(diff - UNI_ADDER[index], UNI_BIT_LENS[index]);
+      return;
+    }
+  }
+}
+
+fn is_plain(c: char) -> bool {
+  c.is_ascii_graphic() || matches!(c, ' ' | '\n' | '\t' | '\r')
+}
+
+pub fn compress(text: &str) -> Vec<u8> {
+  let mut writer = BitWriter::default();
+  // the magic bit
+  writer.push_bit(1);
+
+  let mut state = ALPHA;
+  let mut prev_uni = 0;
+  let mut chars = text.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    if !is_plain(c) {
+      if state != DELTA {
+        if chars.peek().is_some_and(|next| !is_plain(*next)) {
+          // a run of them, uppercase space flips into unicode mode for good
+          if state != ALPHA {
+            writer.switch(state);
+            writer.hcode(ALPHA);
+          }
+          writer.switch(ALPHA);
+          writer.hcode(ALPHA);
+          writer.vcode(1);
+          state = DELTA;
+        } else {
+          writer.switch(state);
+          writer.hcode(DELTA);
+        }
+      }
+      push_unicode(&mut writer, c as u32, prev_uni);
+      prev_uni = c as u32;
+      continue;
+    }
+
+    if state == DELTA {
+      // unicode mode has its own short codes for the common separators
+      let special = match c {
+        ' ' => Some(0),
+        ',' => Some(2),
+        '.' => Some(3),
+        '\n' => Some(4),
+        _ => None,
+      };
+      if let Some(special) = special {
+        writer.step(5, 5);
+        writer.step(special, 4);
+        continue;
+      }
+      writer.switch(DELTA);
+      writer.hcode(ALPHA);
+      state = ALPHA;
+    }
+
+    let byte = c as u8;
+    if byte.is_ascii_alphabetic() {
+      if state != ALPHA {
+        writer.switch(state);
+        writer.hcode(ALPHA);
+        state = ALPHA;
+      }
+      if byte.is_ascii_uppercase() {
+        writer.switch(ALPHA);
+        writer.hcode(ALPHA);
+      }
+      writer.vcode(position(ALPHA, byte.to_ascii_lowercase()).unwrap());
+    } else if byte == b' ' {
+      writer.vcode(if state == NUM { position(NUM, b' ').unwrap() } else { 1 });
+    } else if let Some(index) = position(NUM, byte) {
+      if state != NUM {
+        writer.switch(state);
+        writer.hcode(NUM);
+        if byte.is_ascii_digit() {
+          state = NUM;
+        }
+      }
+      writer.vcode(index);
+    } else if let Some(index) = position(SYM, byte) {
+      writer.switch(state);
+      writer.hcode(SYM);
+      writer.vcode(index);
+    }
+  }
+
+  // a partial last byte needs an end marker or the padding gets decoded as more text
+  if !writer.bits.is_multiple_of(8) {
+    if state != NUM {
+      writer.switch(state);
+      writer.hcode(NUM);
+    }
+    writer.vcode(TERM);
+  }
+
+  writer.bytes
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn round_trip(text: &str) {
+    let compressed = compress(text);
+    assert_eq!(decompress(&compressed).unwrap(), text, "{:02x?}", compressed);
+  }
+
+  #[test]
+  fn round_trips() {
+    for text in [
+      "",
+      "a",
+      "hello world",
+      "Hello World",
+      "SHOUTING INTO THE VOID",
+      "The quick brown fox jumps over the lazy dog.",
+      "meet at 5pm, grid 123-456 (ok?) 100% #1 $5 a=b+c",
+      "line one\nline two\ttabbed\r\nwindows",
+      "{\"key\": [1, 2]} <tag> a_b@c.d ~`^|&*!\\;'",
+      "ümlaut café naïve",
+      "日本語のテキスト",
+      "emoji 👋🔥 ok, then more. 🙂\nnext",
+      "mixed 123abc ABC aBc 9Z",
+    ] {
+      round_trip(text);
+    }
+  }
+
+  #[test]
+  fn plain_text_gets_smaller() {
+    let text = "the quick brown fox jumps over the lazy dog";
+    assert!(compress(text).len() < text.len());
+  }
+
+  #[test]
+  fn repeats_are_capped() {
+    let mut writer = BitWriter::default();
+    writer.push_bit(1);
+    writer.vcode(position(ALPHA, b'a').unwrap());
+    writer.switch(ALPHA);
+    writer.hcode(NUM);
+    writer.vcode(REPEAT);
+    // the biggest count there is, tens of thousands of "a"
+    writer.step(4, 4);
+    writer.push_value(0xffff, 16);
+
+    assert!(decompress(&writer.bytes).is_err());
+  }
+
+  #[test]
+  fn back_references_are_capped() {
+    let mut writer = BitWriter::default();
+    writer.push_bit(1);
+    for c in *b"abcd" {
+      writer.vcode(position(ALPHA, c).unwrap());
+    }
+    writer.switch(ALPHA);
+    writer.hcode(DICT);
+    // length
+    writer.step(4, 4);
+    writer.push_value(0xffff, 16);
+    // distance, the smallest there is
+    writer.step(0, 4);
+    writer.push_value(0, 2);
+
+    assert!(decompress(&writer.bytes).is_err());
+  }
+
+  #[test]
+  fn small_repeats_still_work() {
+    let mut writer = BitWriter::default();
+    writer.push_bit(1);
+    writer.vcode(position(ALPHA, b'z').unwrap());
+    writer.switch(ALPHA);
+    writer.hcode(NUM);
+    writer.vcode(REPEAT);
+    // 1 + 4 more
+    writer.step(0, 4);
+    writer.push_value(1, 2);
+    writer.switch(NUM);
+    writer.hcode(NUM);
+    writer.vcode(TERM);
+
+    assert_eq!(decompress(&writer.bytes).unwrap(), "zzzzzz");
+  }
+
+  #[test]
+  fn garbage_doesnt_panic() {
+    for first in 0..=255u8 {
+      for second in 0..=255u8 {
+        _ = decompress(&[first, second]);
+        _ = decompress(&[first, second, 0xff, 0x00]);
+      }
+    }
+  }
+}
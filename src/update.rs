@@ -10,10 +10,18 @@ use presage::proto::{BodyRange, DataMessage, SyncMessage};
 use presage::store::ContentExt;
 use presage::store::Thread;
 
-use std::ops::Index;
 use std::sync::Arc;
 
-use crate::logger::Logger;
+use chrono::Local;
+use tracing::{debug, info, trace, warn};
+
+use crate::admin::{ChannelsOutcome, handle_channels_command, is_member};
+use crate::direct::signal_dm;
+use crate::messenger::Messenger;
+use crate::metrics::{METRICS, inc};
+use crate::pairing::{signal_link, signal_unlink};
+use crate::shaping::shape;
+use crate::template::{TemplateVars, render};
 use crate::*;
 
 #[derive(PartialEq, Debug)]
@@ -23,6 +31,17 @@ pub enum LinkingAction {
   Fail,
 }
 
+/// Where a command reply should go
+#[derive(Debug, Copy, Clone)]
+pub enum ReplyTo {
+  Group,
+  /// a dm back to a node, through the radio it asked on
+  Node {
+    radio: usize,
+    node: u32,
+  },
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum MessageOption {
   Reply,
@@ -37,25 +56,78 @@ pub enum MessageOption {
 #[derive(Debug)]
 pub enum Action {
   SendToMesh {
+    /// index into `config.radios`, same for every other `radio` in here
+    radio: usize,
     body: String,
     channel: MeshChannel,
     destination: PacketDestination,
     signal_message: Option<SignalMessage>,
+    /// set when its coming back out of the outbox, which keeps it until its actually sent
+    outbox_id: Option<u64>,
   },
 
-  FromRadio(FromRadio),
+  FromRadio {
+    radio: usize,
+    packet: FromRadio,
+  },
 
   SendToGroup {
     message: String,
     ranges: Vec<BodyRange>,
     master_key: GroupMasterKeyBytes,
   },
+  /// a 1:1 signal message
+  SendToContact {
+    uuid: Uuid,
+    message: String,
+  },
 
   MeshAck {
     packet: MeshPacket,
     deliverd: bool,
   },
 
+  // radio connection housekeeping
+  ConnectRadio {
+    radio: usize,
+  },
+  RadioLost {
+    radio: usize,
+  },
+  /// the radio told us which node it is, so we can start sending
+  RadioReady {
+    radio: usize,
+    node: u32,
+  },
+  /// ask a node we dont have a name for to tell us about itself
+  RequestNodeInfo {
+    radio: usize,
+    node: u32,
+  },
+  /// ask the radio for its channels again, in case someone changed them
+  RefreshChannels {
+    radio: usize,
+  },
+  /// a confirmed `/channels` change, for the radio
+  ApplyChannels {
+    radio: usize,
+    channels: Vec<Channel>,
+    description: String,
+    share_psk_with: Option<Uuid>,
+  },
+
+  Status {
+    reply_to: ReplyTo,
+  },
+  /// what `/radio` asks for
+  RadioInfo {
+    reply_to: ReplyTo,
+  },
+  /// send whatever group notices the rate limit lets through
+  FlushGroupNotices,
+  /// see if any mesh nodes have gone quiet
+  CheckQuietNodes,
+
   PickOption,
   DoOption(MessageOption),
 
@@ -64,6 +136,8 @@ pub enum Action {
   ReceiveBatch(Vec<Content>),
 
   Link(LinkingAction),
+  /// more than one thing to do, ie. the same message going out on every radio
+  Batch(Vec<Action>),
   Quit,
 }
 
@@ -176,27 +250,88 @@ pub enum Action {
 //   }
 // }
 
+/// The rest of `body` if its `command`, with or without arguments
+fn command_args<'a>(body: &'a str, command: &str) -> Option<&'a str> {
+  let rest = body.strip_prefix(command)?;
+  (rest.is_empty() || rest.starts_with(' ')).then(|| rest.trim())
+}
+
+/// what people can message the bridge directly with, everything else is for the group
+const DIRECT_COMMANDS: [&str; 3] = ["/link", "/unlink", "/dm"];
+
+/// The text of a data message, or of one this account sent from another device
+fn message_body(content: &Content) -> Option<&str> {
+  match &content.body {
+    ContentBody::DataMessage(DataMessage { body: Some(body), .. })
+    | ContentBody::SynchronizeMessage(SyncMessage {
+      sent: Some(Sent {
+        message: Some(DataMessage { body: Some(body), .. }),
+        ..
+      }),
+      ..
+    }) => Some(body),
+    _ => None,
+  }
+}
+
+/// Whether `handle_message` is going to do anything with this, so we only go looking up the
+/// sender for messages that get bridged or answered
+pub fn wants_message(model: &Model, config: &Config, content: &Content) -> bool {
+  let Some(body) = message_body(content) else {
+    return false;
+  };
+  match Thread::try_from(content) {
+    Ok(Thread::Group(group_key)) => group_key == config.group_key,
+    Ok(Thread::Contact(_)) => {
+      let sender = content.metadata.sender.raw_uuid();
+      sender != model.account.uuid
+        && is_member(model, config, &sender)
+        && DIRECT_COMMANDS
+          .iter()
+          .any(|command| command_args(body, command).is_some())
+    }
+    Err(_) => false,
+  }
+}
+
+/// A plain text answer to a command, back to wherever the command came from
+pub fn reply_to_thread(config: &Config, thread: &Thread, message: String) -> Action {
+  match thread {
+    Thread::Contact(uuid) => Action::SendToContact { uuid: *uuid, message },
+    Thread::Group(_) => Action::SendToGroup {
+      message,
+      ranges: vec![],
+      master_key: config.group_key,
+    },
+  }
+}
+
 pub fn handle_message(model: &mut Model, config: &Config, content: Content) -> Option<Action> {
-  Logger::log("handling message");
-  // Logger::log(format!("Fun Message: {:#?}", content.clone()));
+  // debug!(?content, "fun message");
 
   let ts = content.timestamp();
   let _timestamp = DateTime::from_timestamp_millis(ts as i64).expect("this happens too often");
 
   let Ok(mut thread) = Thread::try_from(&content) else {
-    Logger::log("failed to derive thread from content".to_string());
+    warn!(timestamp = ts, "failed to derive thread from content");
     return None;
   };
 
-  if let Thread::Group(group_key) = thread {
-    if group_key != config.group_key {
+  match thread {
+    Thread::Group(group_key) if group_key != config.group_key => return None,
+    Thread::Group(_) => {}
+    // the copies of what this account sends people itself are none of our business
+    Thread::Contact(_) if content.metadata.sender.raw_uuid() == model.account.uuid => return None,
+    // strangers dont get to link nodes or message the mesh
+    Thread::Contact(_) if !is_member(model, config, &content.metadata.sender.raw_uuid()) => {
+      debug!(sender = %content.metadata.sender.raw_uuid(), "ignoring 1:1 message from outside the group");
       return None;
     }
-  } else {
-    return None;
+    // someone messaging the bridge directly, which only gets a few commands
+    Thread::Contact(_) => {}
   }
 
-  Logger::log(content.body.clone());
+  debug!(timestamp = ts, sender = %content.metadata.sender.raw_uuid(), body = ?content.body, "message in bridged group");
 
   match content.body {
     ContentBody::DataMessage(DataMessage {
@@ -223,7 +358,6 @@ pub fn handle_message(model: &mut Model, config: &Config, content: Content) -> O
       ..
     }) => {
       // Logger::log(format!("heres the body: {}", &body));
-      println!("\tmatched!");
       // Logger::log(format!("DataMessage: {:#?}", body.clone()));
       // some flex-tape on the thread derivation
       // let mut mine = false;
@@ -237,7 +371,6 @@ pub fn handle_message(model: &mut Model, config: &Config, content: Content) -> O
       let _quote = if let Some(Quote { id, .. }) = quote { id } else { None };
 
       let _reactions = if let Some(data_message::Reaction { emoji: Some(emoji), .. }) = reaction {
-        Logger::log("it works like this");
         vec![Reaction {
           emoji: emoji.chars().nth(0)?,
           author: content.metadata.sender.raw_uuid(),
@@ -246,16 +379,56 @@ pub fn handle_message(model: &mut Model, config: &Config, content: Content) -> O
         vec![]
       };
 
+      let sender = content.metadata.sender.raw_uuid();
+      if let Some(code) = command_args(&body, "/link") {
+        info!(command = "/link", %sender, "signal command");
+        return Some(signal_link(model, config, &thread, sender, code));
+      }
+      if let Some(args) = command_args(&body, "/unlink") {
+        info!(command = "/unlink", %sender, "signal command");
+        return Some(signal_unlink(model, config, &thread, sender, args));
+      }
+      if let Some(args) = command_args(&body, "/dm") {
+        info!(command = "/dm", %sender, "signal command");
+        return Some(signal_dm(model, config, &thread, sender, timestamp?, args));
+      }
+      // everything else only makes sense in the group
+      if let Thread::Contact(_) = thread {
+        return None;
+      }
+
       match body.as_str() {
         "/channel" => {
-          Logger::log("semding channel info...");
-          let channel = model.channels.index(config.channel_index);
-          return Some(Action::SendToGroup {
-            message: format!(
-              "Channel Details:\nname: {},\npsk: {}",
-              channel.name,
+          info!(command = "/channel", sender = %content.metadata.sender.raw_uuid(), "signal command");
+          let mut details = vec![];
+          for (state, radio) in model.radios.iter().zip(&config.radios) {
+            let Some(channel) = state
+              .channels
+              .bridged(config, radio)
+              .and_then(|index| state.channels.settings(index))
+            else {
+              continue;
+            };
+            let mut detail = String::new();
+            if config.radios.len() > 1 {
+              detail.push_str(&format!("radio: {},\n", state.name));
+            }
+            detail.push_str(&format!(
+              "name: {},\npsk: {}",
+              state.channels.display_name(channel),
               BASE64_STANDARD.encode(channel.psk.clone())
-            ),
+            ));
+            details.push(detail);
+          }
+          if details.is_empty() {
+            return Some(Action::SendToGroup {
+              message: "the radio doesnt have the bridged channel right now".to_string(),
+              ranges: vec![],
+              master_key: config.group_key,
+            });
+          }
+          return Some(Action::SendToGroup {
+            message: format!("Channel Details:\n{}", details.join("\n\n")),
             ranges: vec![BodyRange {
               start: Some(0),
               length: Some(16),
@@ -264,13 +437,55 @@ pub fn handle_message(model: &mut Model, config: &Config, content: Content) -> O
             master_key: config.group_key,
           });
         } // "/qr" => return Some(Action::SendToGroup { message:"qr" , master_key: config.group_key })
+        command if command == "/channels" || command.starts_with("/channels ") => {
+          let sender = content.metadata.sender.raw_uuid();
+          info!(command = "/channels", %sender, "signal command");
+          return Some(
+            match handle_channels_command(model, config, sender, &command["/channels".len()..]) {
+              ChannelsOutcome::Reply(message) => Action::SendToGroup {
+                message,
+                ranges: vec![],
+                master_key: config.group_key,
+              },
+              ChannelsOutcome::Apply {
+                radio,
+                channels,
+                description,
+                share_psk_with,
+              } => Action::ApplyChannels {
+                radio,
+                channels,
+                description,
+                share_psk_with,
+              },
+            },
+          );
+        }
+        "/status" => {
+          info!(command = "/status", sender = %content.metadata.sender.raw_uuid(), "signal command");
+          return Some(Action::Status {
+            reply_to: ReplyTo::Group,
+          });
+        }
+        "/radio" => {
+          info!(command = "/radio", sender = %content.metadata.sender.raw_uuid(), "signal command");
+          return Some(Action::RadioInfo {
+            reply_to: ReplyTo::Group,
+          });
+        }
         "/help" => {
-          Logger::log("being helpful...");
+          info!(command = "/help", sender = %content.metadata.sender.raw_uuid(), "signal command");
           let help_text_lines = vec![
             "Interact with the meshtastci-2-signal gateway bot",
             "",
             "Commands:",
             "\t/channel\t\tDisplay information about the meshtastic channel",
+            "\t/channels\t\tList or change the radios channels (admins only)",
+            "\t/status\t\tDisplay the health of the gateway",
+            "\t/radio\t\tDisplay the radios hardware and settings",
+            "\t/link [code]\t\tLink your radio to your signal account",
+            "\t/unlink [!nodeid]\t\tUndo a link",
+            "\t/dm <node> <message>\t\tSend a message to just one node, by id or name",
             "\t/help\t\tDisplay this help message",
           ];
 
@@ -298,29 +513,64 @@ pub fn handle_message(model: &mut Model, config: &Config, content: Content) -> O
       // }
       let uuid = content.metadata.sender.raw_uuid();
 
-      println!("looking for this key: {:?}", content.metadata.sender);
-
-      let name = if model.contacts.contains_key(&uuid) {
-        model.contacts[&uuid].name.clone().unwrap().given_name
-      } else {
-        format!("{:?}", content.metadata.sender)
+      let name = shape(&model.names.get(&uuid), &config.shaping);
+      let short: String = name.chars().take(4).collect();
+      let id = uuid.simple().to_string();
+      let time = Local::now().format("%H:%M").to_string();
+      let shaped = shape(&body, &config.shaping);
+      let signal_message = SignalMessage {
+        body: body,
+        sender: uuid,
+        // kaboom?
+        timestamp: timestamp?,
+        contact: None,
+        direct: false,
       };
 
-      let message: String = format!("{}:\n{}", name, body);
+      // every radio gets its own copy, on whichever channel it bridges
+      let mut sends = vec![];
+      for (radio, (state, radio_config)) in model.radios.iter().zip(&config.radios).enumerate() {
+        let Some(index) = state.channels.bridged(config, radio_config) else {
+          warn!(sender = %uuid, radio = %state.name, "bridged channel isnt on the radio, not sending");
+          continue;
+        };
+
+        let message = render(
+          &config.templates.signal_to_mesh,
+          &TemplateVars {
+            name: &name,
+            short: &short,
+            id: &id[..8],
+            channel: state.channels.name(index).unwrap_or_default(),
+            radio: &state.name,
+            time: &time,
+            body: &shaped,
+            ..Default::default()
+          },
+        )
+        .text;
+
+        sends.push(Action::SendToMesh {
+          radio,
+          body: message,
+          channel: index.into(),
+          destination: PacketDestination::Broadcast,
+          signal_message: Some(signal_message.clone()),
+          outbox_id: None,
+        });
+      }
 
-      Logger::log("broadcasting to mesh...");
+      if sends.is_empty() {
+        return None;
+      }
+      info!(sender = %uuid, timestamp = ?timestamp, radios = sends.len(), "bridging signal message to mesh");
+      inc(&METRICS.signal_to_mesh);
 
-      return Some(Action::SendToMesh {
-        body: message,
-        channel: 1.into(),
-        destination: PacketDestination::Broadcast,
-        signal_message: Some(SignalMessage {
-          body: body,
-          sender: uuid,
-          // kaboom?
-          timestamp: timestamp?,
-        }),
-      });
+      return if sends.len() == 1 {
+        sends.pop()
+      } else {
+        Some(Action::Batch(sends))
+      };
 
       // insert_message(model, data, thread, ts, mine)
     }
@@ -357,18 +607,19 @@ pub fn handle_message(model: &mut Model, config: &Config, content: Content) -> O
   None
 }
 
-pub async fn update_contacts(model: &mut Model, spawner: &SignalSpawner) -> anyhow::Result<()> {
-  Logger::log("updating contacts".to_string());
+pub async fn update_contacts(model: &mut Model, spawner: &impl Messenger) -> anyhow::Result<()> {
+  debug!("updating contacts");
   for contact in spawner.list_contacts().await? {
     // Logger::log(format!("{}", contact.inbox_position));
+    model.names.remember_contact(&contact);
     if model.contacts.contains_key(&contact.uuid) {
-      Logger::log("already_gyatt key".to_string());
+      trace!(uuid = %contact.uuid, "already have contact");
       continue;
     } else {
       let profile_key = match contact.profile_key.clone().try_into() {
         Ok(bytes) => Some(ProfileKey::create(bytes)),
         Err(_) => {
-          Logger::log(format!("died on this dude: {:#?}", contact));
+          warn!(uuid = %contact.uuid, "contact has a bad profile key");
           continue;
         }
       };
@@ -379,7 +630,7 @@ pub async fn update_contacts(model: &mut Model, spawner: &SignalSpawner) -> anyh
       };
 
       let Some(contacts) = Arc::get_mut(&mut model.contacts) else {
-        Logger::log("didnt get off so easy".to_string());
+        warn!("contacts are shared, cant update them");
         return Ok(());
       };
 
@@ -390,12 +641,12 @@ pub async fn update_contacts(model: &mut Model, spawner: &SignalSpawner) -> anyh
 }
 
 impl Model {
-  pub async fn update_groups(self: &mut Self, spawner: &SignalSpawner) -> anyhow::Result<()> {
-    Logger::log("updating groups".to_string());
+  pub async fn update_groups(self: &mut Self, spawner: &impl Messenger) -> anyhow::Result<()> {
+    debug!("updating groups");
     for (key, group) in spawner.list_groups().await {
       if !self.groups.contains_key(&key) {}
       let Some(groups) = Arc::get_mut(&mut self.groups) else {
-        Logger::log("didnt get off so easy".to_string());
+        warn!("groups are shared, cant update them");
         continue;
       };
 